@@ -0,0 +1,46 @@
+//! Rendering benchmarks, demonstrating that rendering time grows linearly with the number of
+//! instructions. Run with `cargo bench --bench render`.
+
+use std::{
+    hint::black_box,
+    io,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use dockerfile::{
+    Dockerfile,
+    Run,
+};
+
+const SIZES: &[usize] = &[1_000, 10_000, 100_000, 1_000_000];
+
+fn dockerfile_with(instructions: usize) -> Dockerfile {
+    let runs: Vec<_> = (0..instructions)
+        .map(|idx| Run::new(format!("echo 'instruction number {}' >> /var/log/build.log", idx)))
+        .collect();
+    Dockerfile::base("rust:1.31-slim").append(runs).finish()
+}
+
+/// Time the given closure, returning the fastest of a few runs.
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    (0..5).map(|_| {
+        let start = Instant::now();
+        f();
+        start.elapsed()
+    }).min().unwrap()
+}
+
+fn main() {
+    println!("{:>10} {:>16} {:>16}", "instrs", "write_to ns/op", "to_string ns/op");
+    for &size in SIZES {
+        let dockerfile = dockerfile_with(size);
+        let write_to = time(|| dockerfile.write_to(&mut black_box(io::sink())).unwrap());
+        let to_string = time(|| { black_box(dockerfile.to_string()); });
+        println!("{:>10} {:>16.1} {:>16.1}", size,
+            write_to.as_nanos() as f64 / size as f64,
+            to_string.as_nanos() as f64 / size as f64);
+    }
+}
@@ -0,0 +1,337 @@
+use std::ops::Range;
+
+use crate::{
+    builder::{
+        Dockerfile,
+        DockerfileBuilder,
+    },
+    instructions::Instruction,
+    render::{
+        self,
+        split_words,
+    },
+    stage::Stage,
+};
+
+/// The scope in which an `ARG` was declared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ArgScope {
+    /// Declared before the first `FROM`, as added by `DockerfileBuilder::push_initial_arg`.
+    ///
+    /// Global arguments are visible only within `FROM` instructions, unless redeclared within a
+    /// stage.
+    Global,
+
+    /// Declared within the stage of the given index, and visible only within it.
+    Stage(usize),
+}
+
+/// A build argument declared by an `ARG` instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArgDecl<'a> {
+    /// The name of the argument.
+    pub name: &'a str,
+
+    /// The default value given in the declaration, without any surrounding quotes.
+    pub default: Option<&'a str>,
+
+    /// The index of the declaring `ARG` instruction within its Dockerfile.
+    pub index: usize,
+
+    /// The scope of the declaration.
+    pub scope: ArgScope,
+}
+
+/// A build argument in scope at some position of a Dockerfile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArgInScope<'a> {
+    /// The declaration which brought the argument into scope, or `None` for one of the arguments
+    /// Docker predefines, which are in scope without being declared.
+    pub declaration: Option<ArgDecl<'a>>,
+
+    /// The default value of the argument.
+    ///
+    /// A stage declaration without a default inherits the default of a global declaration of the
+    /// same name, which is the usual way of making a global argument visible within a stage.
+    pub default: Option<&'a str>,
+}
+
+/// The platform arguments which Docker predefines in the global scope. Like any other global
+/// argument, they are visible within a stage only once redeclared there.
+const PLATFORM_ARGS: &[&str] = &[
+    "BUILDPLATFORM", "BUILDOS", "BUILDARCH", "BUILDVARIANT",
+    "TARGETPLATFORM", "TARGETOS", "TARGETARCH", "TARGETVARIANT",
+];
+
+/// The proxy arguments which Docker predefines, which are visible everywhere without being
+/// declared.
+const PROXY_ARGS: &[&str] = &[
+    "HTTP_PROXY", "http_proxy", "HTTPS_PROXY", "https_proxy", "FTP_PROXY", "ftp_proxy",
+    "NO_PROXY", "no_proxy", "ALL_PROXY", "all_proxy",
+];
+
+impl Dockerfile {
+    /// The arguments declared before the first `FROM`, in order.
+    pub fn global_args(&self) -> Vec<ArgDecl<'_>> {
+        let instructions = scoped(self.instructions().iter());
+        declarations(&instructions, 0..preamble_end(&instructions), ArgScope::Global, render::escape_char(self))
+    }
+
+    /// Find the build argument with the given name which is in scope for the instruction at the
+    /// given index.
+    ///
+    /// This follows Docker's rules. `FROM` instructions, and anything else before the first
+    /// `FROM`, see the global arguments. Every other instruction sees only the arguments declared
+    /// earlier within its own stage, so a global argument is out of scope within a stage until it
+    /// is redeclared there. Later declarations of a name replace earlier ones. Note that an `ENV`
+    /// of the same name takes precedence over an argument when the instruction runs, which this
+    /// does not account for.
+    ///
+    /// Docker's predefined arguments are in scope too. The platform arguments, such as
+    /// `BUILDPLATFORM` and `TARGETARCH`, are global, while the proxy arguments, such as
+    /// `HTTP_PROXY`, are in scope everywhere.
+    pub fn arg_in_scope(&self, index: usize, name: &str) -> Option<ArgInScope<'_>> {
+        self.instructions().get(index)?;
+        in_scope(&scoped(self.instructions().iter()), render::escape_char(self), index, name)
+    }
+}
+
+impl<'a> Stage<'a> {
+    /// The arguments declared within this stage, in order.
+    pub fn args(&self) -> Vec<ArgDecl<'a>> {
+        let instructions = scoped(self.dockerfile().instructions().iter());
+        declarations(&instructions, self.range(), ArgScope::Stage(self.index()), render::escape_char(self.dockerfile()))
+    }
+
+    /// Find the build argument with the given name which is in scope at the end of this stage.
+    ///
+    /// See `Dockerfile::arg_in_scope` for the rules followed.
+    pub fn arg_in_scope(&self, name: &str) -> Option<ArgInScope<'a>> {
+        let instructions = scoped(self.dockerfile().instructions()[..self.range().end].iter());
+        in_scope(&instructions, render::escape_char(self.dockerfile()), self.range().end, name)
+    }
+}
+
+impl DockerfileBuilder {
+    /// Find the build argument with the given name which is in scope for the `FROM` of every
+    /// stage, being one of the initial `ARG`s or a predefined argument.
+    ///
+    /// See `Dockerfile::arg_in_scope` for the rules followed. The index of a declaration is the
+    /// one it will have in the finished Dockerfile.
+    pub fn global_arg_in_scope(&self, name: &str) -> Option<ArgInScope<'_>> {
+        let instructions = self.scoped();
+        in_scope(&instructions, self.escape_char(), preamble_end(&instructions), name)
+    }
+
+    /// Find the build argument with the given name which is in scope for the next instruction
+    /// pushed, within the stage added last.
+    ///
+    /// See `Dockerfile::arg_in_scope` for the rules followed. The index of a declaration is the
+    /// one it will have in the finished Dockerfile.
+    pub fn arg_in_scope(&self, name: &str) -> Option<ArgInScope<'_>> {
+        let instructions = self.scoped();
+        in_scope(&instructions, self.escape_char(), instructions.len(), name)
+    }
+
+    /// The instructions added so far, in the order `finish` gives them.
+    fn scoped(&self) -> Vec<Scoped<'_>> {
+        let directives = self.initial_directives.iter().flatten().map(|_| Scoped::Other);
+        let args = self.initial_args.iter().flatten().map(|(arg, _)| Scoped::Arg(arg.value()));
+        let instructions = scoped(self.instructions.iter().flatten().map(|(inst, _)| inst));
+        directives.chain(args).chain(Some(Scoped::From)).chain(instructions).collect()
+    }
+
+    /// The escape character declared by the initial directives, if any.
+    fn escape_char(&self) -> char {
+        let directives: Vec<_> = self.initial_directives.iter().flatten().map(|(directive, _)| Instruction::Directive(directive.clone())).collect();
+        render::escape_char_of(&directives)
+    }
+}
+
+/// An instruction, as far as the scope of build arguments is concerned.
+#[derive(Clone, Copy)]
+enum Scoped<'a> {
+    /// An `ARG`, with its raw arguments.
+    Arg(&'a str),
+    From,
+    Other,
+}
+
+/// View the given instructions as far as the scope of build arguments is concerned.
+fn scoped<'a, I: Iterator<Item = &'a Instruction>>(instructions: I) -> Vec<Scoped<'a>> {
+    instructions.map(|inst| match inst {
+        Instruction::Arg(_) => Scoped::Arg(inst.value()),
+        Instruction::From(_) => Scoped::From,
+        _ => Scoped::Other,
+    }).collect()
+}
+
+/// The index of the first `FROM`, which ends the global scope.
+fn preamble_end(instructions: &[Scoped<'_>]) -> usize {
+    instructions.iter().position(|inst| matches!(inst, Scoped::From)).unwrap_or(instructions.len())
+}
+
+/// Find the build argument with the given name which is in scope at the given index, which may
+/// be one past the last instruction.
+fn in_scope<'a>(instructions: &[Scoped<'a>], escape: char, index: usize, name: &str) -> Option<ArgInScope<'a>> {
+    let globals = declarations(instructions, 0..preamble_end(instructions), ArgScope::Global, escape);
+    let last_global = || globals.iter().rev().find(|decl| decl.name == name && decl.index < index).copied();
+    let predefined_arg = ArgInScope{declaration: None, default: None};
+    let start = match instructions.get(index) {
+        Some(Scoped::From) => None,
+        _ => instructions[..index].iter().rposition(|inst| matches!(inst, Scoped::From)),
+    };
+    let start = match start {
+        Some(start) => start,
+        None => {
+            let global = last_global().map(|declaration| ArgInScope{declaration: Some(declaration), default: declaration.default});
+            let predefined = PLATFORM_ARGS.contains(&name) || PROXY_ARGS.contains(&name);
+            return global.or_else(|| predefined.then_some(predefined_arg));
+        }
+    };
+    let stage = instructions[..start].iter().filter(|inst| matches!(inst, Scoped::From)).count();
+    let declarations = declarations(instructions, start..index, ArgScope::Stage(stage), escape);
+    match declarations.into_iter().rev().find(|decl| decl.name == name) {
+        Some(declaration) => {
+            let default = declaration.default.or_else(|| last_global().and_then(|global| global.default));
+            Some(ArgInScope{declaration: Some(declaration), default})
+        }
+        None => PROXY_ARGS.contains(&name).then_some(predefined_arg),
+    }
+}
+
+/// Collect the declarations of the `ARG` instructions within the given range of instructions.
+fn declarations<'a>(instructions: &[Scoped<'a>], range: Range<usize>, scope: ArgScope, escape: char) -> Vec<ArgDecl<'a>> {
+    let mut decls = vec![];
+    for index in range {
+        if let Scoped::Arg(value) = instructions[index] {
+            for (_, word) in split_words(value, escape) {
+                let (name, default) = match word.find('=') {
+                    Some(eq) => (&word[..eq], Some(unquote(&word[eq + 1..]))),
+                    None => (word, None),
+                };
+                decls.push(ArgDecl{name, default, index, scope});
+            }
+        }
+    }
+    decls
+}
+
+/// Remove a single pair of quotes surrounding the whole of the given value.
+pub(crate) fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instructions::{
+        Arg,
+        Directive,
+    };
+
+    const ARGS: &str = r##"ARG RUST_VERSION=1.75
+ARG PROFILE="release build"
+FROM rust:${RUST_VERSION} AS builder
+RUN echo $RUST_VERSION
+ARG RUST_VERSION
+ARG TARGET=x86_64 EXTRA
+RUN echo $RUST_VERSION $TARGET
+FROM alpine:${RUST_VERSION}
+ARG PROFILE=debug
+RUN echo $PROFILE
+"##;
+
+    #[test]
+    fn args_are_declared_per_scope() {
+        let dockerfile = Dockerfile::parse(ARGS).unwrap();
+        assert_eq!(dockerfile.global_args(), vec![
+            ArgDecl{name: "RUST_VERSION", default: Some("1.75"), index: 0, scope: ArgScope::Global},
+            ArgDecl{name: "PROFILE", default: Some("release build"), index: 1, scope: ArgScope::Global},
+        ]);
+        let names: Vec<_> = dockerfile.stages()[0].args().iter().map(|decl| (decl.name, decl.default)).collect();
+        assert_eq!(names, vec![("RUST_VERSION", None), ("TARGET", Some("x86_64")), ("EXTRA", None)]);
+        assert_eq!(dockerfile.stages()[1].args()[0].scope, ArgScope::Stage(1));
+    }
+
+    #[test]
+    fn arg_in_scope_follows_docker() {
+        let dockerfile = Dockerfile::parse(ARGS).unwrap();
+        let default = |index, name| dockerfile.arg_in_scope(index, name).map(|arg| (arg.declaration.map(|decl| decl.index), arg.default));
+
+        // Global arguments are visible in FROM lines.
+        assert_eq!(default(2, "RUST_VERSION"), Some((Some(0), Some("1.75"))));
+        assert_eq!(default(7, "RUST_VERSION"), Some((Some(0), Some("1.75"))));
+        // But not within a stage until they are redeclared, which inherits the global default.
+        assert_eq!(default(3, "RUST_VERSION"), None);
+        assert_eq!(default(6, "RUST_VERSION"), Some((Some(4), Some("1.75"))));
+        assert_eq!(default(6, "TARGET"), Some((Some(5), Some("x86_64"))));
+        // Stage declarations with a default replace the global default.
+        assert_eq!(default(9, "PROFILE"), Some((Some(8), Some("debug"))));
+        // Declarations only take effect after the declaring instruction.
+        assert_eq!(default(8, "PROFILE"), None);
+        assert_eq!(default(1, "PROFILE"), None);
+        assert_eq!(default(10, "PROFILE"), None);
+    }
+
+    #[test]
+    fn predefined_args_are_in_scope() {
+        let dockerfile = Dockerfile::parse(r##"FROM --platform=$BUILDPLATFORM golang AS build
+RUN echo $TARGETARCH $HTTP_PROXY $no_proxy
+ARG TARGETARCH
+RUN GOARCH=$TARGETARCH go build
+"##).unwrap();
+        let scope = |index, name| dockerfile.arg_in_scope(index, name).map(|arg| arg.declaration.map(|decl| decl.index));
+
+        // Platform arguments are global, so are seen by FROM lines but not within a stage until
+        // they are redeclared.
+        assert_eq!(scope(0, "BUILDPLATFORM"), Some(None));
+        assert_eq!(scope(1, "TARGETARCH"), None);
+        assert_eq!(scope(3, "TARGETARCH"), Some(Some(2)));
+        // Proxy arguments are seen everywhere, in either case.
+        assert_eq!(scope(0, "HTTP_PROXY"), Some(None));
+        assert_eq!(scope(1, "HTTP_PROXY"), Some(None));
+        assert_eq!(scope(1, "no_proxy"), Some(None));
+        assert_eq!(scope(1, "Http_Proxy"), None);
+        assert_eq!(dockerfile.stages()[0].arg_in_scope("TARGETARCH").map(|arg| arg.declaration.map(|decl| decl.index)), Some(Some(2)));
+    }
+
+    #[test]
+    fn stage_arg_in_scope() {
+        let dockerfile = Dockerfile::parse(ARGS).unwrap();
+        let default = |stage: usize, name| dockerfile.stages()[stage].arg_in_scope(name).map(|arg| arg.default);
+        assert_eq!(default(0, "RUST_VERSION"), Some(Some("1.75")));
+        assert_eq!(default(0, "PROFILE"), None);
+        assert_eq!(default(1, "PROFILE"), Some(Some("debug")));
+        assert_eq!(default(1, "TARGET"), None);
+    }
+
+    #[test]
+    fn builder_arg_in_scope() {
+        let builder = Dockerfile::base("rust:${RUST_VERSION}")
+            .push_initial_directive(Directive::new("escape=`"))
+            .push_initial_arg(Arg::new("RUST_VERSION=1.75 PROFILE"))
+            .push(Arg::new("RUST_VERSION"));
+        let global = builder.global_arg_in_scope("RUST_VERSION").unwrap();
+        assert_eq!(global.declaration, Some(ArgDecl{name: "RUST_VERSION", default: Some("1.75"), index: 1, scope: ArgScope::Global}));
+        assert_eq!(builder.global_arg_in_scope("TARGETPLATFORM"), Some(ArgInScope{declaration: None, default: None}));
+        assert_eq!(builder.arg_in_scope("RUST_VERSION").map(|arg| (arg.declaration.unwrap().index, arg.default)), Some((3, Some("1.75"))));
+        assert_eq!(builder.arg_in_scope("PROFILE"), None);
+
+        let builder = builder.stage("runtime", "debian", |stage| stage.push(Arg::new("PROFILE=release")));
+        assert_eq!(builder.arg_in_scope("RUST_VERSION"), None);
+        assert_eq!(builder.arg_in_scope("https_proxy"), Some(ArgInScope{declaration: None, default: None}));
+        let profile = builder.arg_in_scope("PROFILE").unwrap().declaration.unwrap();
+        assert_eq!((profile.index, profile.scope, profile.default), (5, ArgScope::Stage(1), Some("release")));
+        let dockerfile = builder.finish();
+        assert_eq!(dockerfile.stages()[1].arg_in_scope("PROFILE").map(|arg| arg.declaration.unwrap().index), Some(5));
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     borrow::Cow,
     fmt,
+    panic::Location,
 };
 
 use crate::{
@@ -12,51 +13,120 @@ use crate::{
     },
 };
 
+/// The location in Rust source code at which an instruction was added to a builder.
+pub type Provenance = &'static Location<'static>;
+
 /// A Dockerfile represented in code.
 ///
 /// A Dockerfile, conceptually, is a series of instructions. In code, that is exactly how they are
 /// represented here. A wrapper around a `Vec<Instruction>` with a few convenience methods and
 /// such.
-pub struct Dockerfile(Vec<Instruction>);
+///
+/// Dockerfiles built with a `DockerfileBuilder` also remember where in the Rust source each of
+/// their instructions was added. This provenance is ignored when comparing Dockerfiles.
+#[derive(Clone, Debug)]
+pub struct Dockerfile {
+    pub(crate) instructions: Vec<Instruction>,
+
+    /// The provenance of each instruction, in step with `instructions`.
+    pub(crate) provenance: Vec<Option<Provenance>>,
+
+    /// The 1-based line on which each instruction began, in step with `instructions` for parsed
+    /// Dockerfiles, and empty otherwise.
+    pub(crate) lines: Vec<Option<usize>>,
+}
 
 impl fmt::Display for Dockerfile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0.iter().fold(String::new(), |acc, elem| acc + &elem.to_string()))
+        self.write_to_fmt(f)
+    }
+}
+
+impl PartialEq for Dockerfile {
+    fn eq(&self, other: &Self) -> bool {
+        self.instructions == other.instructions
     }
 }
 
+impl Eq for Dockerfile {}
+
 impl Dockerfile {
     /// Start building a new Dockerfile from the specified base image.
     ///
     /// Only requirement is the initial `FROM` instruction. Call `.finish()` when complete.
+    #[track_caller]
     pub fn base<T: Into<Cow<'static, str>>>(from: T) -> DockerfileBuilder {
         DockerfileBuilder{
             initial_directives: None,
             initial_args: None,
-            from: From::new(from),
+            from: (From::new(from), Location::caller()),
             instructions: None,
         }
     }
+
+    /// Create a Dockerfile from the given instructions, which have no known provenance.
+    pub(crate) fn from_instructions(instructions: Vec<Instruction>) -> Self {
+        let provenance = vec![None; instructions.len()];
+        Dockerfile{instructions, provenance, lines: vec![]}
+    }
+
+    /// The instructions of this Dockerfile, in the order in which they will be rendered.
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// The location in Rust source code at which the instruction at the given index was added.
+    ///
+    /// This is only known for Dockerfiles built with a `DockerfileBuilder`.
+    pub fn provenance(&self, index: usize) -> Option<Provenance> {
+        self.provenance.get(index).copied().flatten()
+    }
+
+    /// The 1-based line on which the instruction at the given index began in the text it was
+    /// parsed from.
+    ///
+    /// This is only known for Dockerfiles parsed with `Dockerfile::parse` and friends.
+    pub fn source_line(&self, index: usize) -> Option<usize> {
+        self.lines.get(index).copied().flatten()
+    }
+
+    /// The line to report problems with each instruction against, in order.
+    ///
+    /// This is the line it was parsed from when known, and otherwise the line on which it begins
+    /// when rendered with the default options.
+    pub(crate) fn report_lines(&self) -> Vec<usize> {
+        let mut rendered = 1;
+        self.instructions.iter().enumerate().map(|(index, instruction)| {
+            let line = self.source_line(index).unwrap_or(rendered);
+            rendered += 1 + instruction.value().matches('\n').count();
+            line
+        }).collect()
+    }
 }
 
 /// A Dockerfile builder.
+///
+/// Every method which adds instructions records the location of its caller, which is available
+/// from the finished Dockerfile via `Dockerfile::provenance`.
 pub struct DockerfileBuilder {
     /// Any parser directives.
-    initial_directives: Option<Vec<Directive>>,
+    pub(crate) initial_directives: Option<Vec<(Directive, Provenance)>>,
 
     /// Any initial `ARG`s which are to appear before the initial `FROM` instruction.
-    initial_args: Option<Vec<Arg>>,
+    pub(crate) initial_args: Option<Vec<(Arg, Provenance)>>,
 
     /// The new Dockerfile's initial `FROM` instruction.
-    from: From,
+    pub(crate) from: (From, Provenance),
 
     /// Any additional instructions part of the Dockerfile.
-    instructions: Option<Vec<Instruction>>,
+    pub(crate) instructions: Option<Vec<(Instruction, Provenance)>>,
 }
 
 impl DockerfileBuilder {
     /// Push a new initial directive to this Dockerfile instance.
+    #[track_caller]
     pub fn push_initial_directive(mut self, directive: Directive) -> Self {
+        let directive = (directive, Location::caller());
         if let Some(ref mut directives) = self.initial_directives {
             directives.push(directive);
         } else {
@@ -66,7 +136,9 @@ impl DockerfileBuilder {
     }
 
     /// Push a new initial arg to this Dockerfile instance.
+    #[track_caller]
     pub fn push_initial_arg(mut self, arg: Arg) -> Self {
+        let arg = (arg, Location::caller());
         if let Some(ref mut args) = self.initial_args {
             args.push(arg);
         } else {
@@ -76,18 +148,27 @@ impl DockerfileBuilder {
     }
 
     /// Push a new instruction into this Dockerfile instance.
-    pub fn push<I: Into<Instruction>>(mut self, instruction: I) -> Self {
+    #[track_caller]
+    pub fn push<I: Into<Instruction>>(self, instruction: I) -> Self {
+        self.push_located(instruction.into(), Location::caller())
+    }
+
+    /// Push a new instruction which was added at the given location.
+    pub(crate) fn push_located(mut self, instruction: Instruction, location: Provenance) -> Self {
+        let instruction = (instruction, location);
         if let Some(ref mut instructions) = self.instructions {
-            instructions.push(instruction.into());
+            instructions.push(instruction);
         } else {
-            self.instructions = Some(vec![instruction.into()]);
+            self.instructions = Some(vec![instruction]);
         }
         self
     }
 
     /// Append a vector of instructions to this Dockerfile instance.
+    #[track_caller]
     pub fn append<I: Into<Instruction>>(mut self, new: Vec<I>) -> Self {
-        let mut new = new.into_iter().map(Into::into).collect();
+        let location = Location::caller();
+        let mut new = new.into_iter().map(|inst| (inst.into(), location)).collect();
         if let Some(ref mut instructions) = self.instructions {
             instructions.append(&mut new);
         } else {
@@ -99,24 +180,26 @@ impl DockerfileBuilder {
     /// Generate the output Dockerfile as a string.
     pub fn finish(self) -> Dockerfile {
         // Add directives to the Dockerfile.
-        let mut all_instructions: Vec<Instruction> = vec![];
+        let mut all_instructions: Vec<(Instruction, Provenance)> = vec![];
         if let Some(directives) = self.initial_directives {
-            all_instructions.extend(directives.into_iter().map(|inst| Instruction::Directive(inst)));
+            all_instructions.extend(directives.into_iter().map(|(inst, loc)| (Instruction::Directive(inst), loc)));
         }
 
         // Add initial args to the Dockerfile.
         if let Some(args) = self.initial_args {
-            all_instructions.extend(args.into_iter().map(|inst| Instruction::Arg(inst)));
+            all_instructions.extend(args.into_iter().map(|(inst, loc)| (Instruction::Arg(inst), loc)));
         }
 
         // Add from instruction to Dockerfile.
-        all_instructions.push(Instruction::From(self.from));
+        let (from, loc) = self.from;
+        all_instructions.push((Instruction::From(from), loc));
 
         // Append any other instructions in serial order.
         if let Some(instructions) = self.instructions {
-            all_instructions.extend(instructions.into_iter());
+            all_instructions.extend(instructions);
         }
 
-        Dockerfile(all_instructions)
+        let (instructions, provenance) = all_instructions.into_iter().map(|(inst, loc)| (inst, Some(loc))).unzip();
+        Dockerfile{instructions, provenance, lines: vec![]}
     }
 }
@@ -0,0 +1,102 @@
+use std::{
+    fmt,
+    ops::Range,
+};
+
+/// The severity of a diagnostic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Something which is likely to be a mistake, but which Docker will accept.
+    Warning,
+    /// Something which Docker will reject.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a Dockerfile, along with the line on which it was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// How severe this problem is.
+    pub severity: Severity,
+
+    /// The 1-based line number of the source line which this diagnostic refers to.
+    pub line: usize,
+
+    /// A human readable description of the problem.
+    pub message: String,
+
+    /// The ID of the lint rule which found this problem, if any.
+    pub rule: Option<&'static str>,
+
+    /// The part of the Dockerfile this diagnostic refers to, if known more precisely than a line.
+    pub span: Option<Span>,
+
+    /// A suggested fix for the problem, if any, which `Display` writes on a line of its own
+    /// beginning with `help:`.
+    pub suggestion: Option<String>,
+}
+
+/// A part of a single instruction of a Dockerfile.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The index of the instruction within its Dockerfile.
+    pub instruction: usize,
+
+    /// The byte range within the instruction's arguments, as given by `Instruction::value`.
+    pub range: Range<usize>,
+}
+
+impl Diagnostic {
+    /// Create a new error diagnostic for the given line.
+    pub fn error<T: Into<String>>(line: usize, message: T) -> Self {
+        Diagnostic{severity: Severity::Error, line, message: message.into(), rule: None, span: None, suggestion: None}
+    }
+
+    /// Create a new warning diagnostic for the given line.
+    pub fn warning<T: Into<String>>(line: usize, message: T) -> Self {
+        Diagnostic{severity: Severity::Warning, line, message: message.into(), rule: None, span: None, suggestion: None}
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.rule {
+            Some(rule) => write!(f, "{}[{}]: line {}: {}", self.severity, rule, self.line, self.message)?,
+            None => write!(f, "{}: line {}: {}", self.severity, self.line, self.message)?,
+        }
+        match &self.suggestion {
+            Some(suggestion) => write!(f, "\n  help: {}", suggestion),
+            None => Ok(()),
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display_includes_rule_and_suggestion() {
+        let diagnostic = Diagnostic::error(3, "unknown instruction: RUNN");
+        assert_eq!(diagnostic.to_string(), "error: line 3: unknown instruction: RUNN");
+
+        let diagnostic = Diagnostic{
+            rule: Some("NoSudo"),
+            suggestion: Some("Remove sudo, and set USER for the commands which need another user".to_string()),
+            ..Diagnostic::warning(2, "Avoid sudo, as builds already run as root")
+        };
+        assert_eq!(diagnostic.to_string(),
+            "warning[NoSudo]: line 2: Avoid sudo, as builds already run as root\n  help: Remove sudo, and set USER for the commands which need another user");
+    }
+}
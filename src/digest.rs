@@ -0,0 +1,293 @@
+use std::fmt::{
+    self,
+    Write,
+};
+
+use crate::{
+    builder::Dockerfile,
+    fmt::format,
+    form::Form,
+    instructions::Instruction,
+    parser,
+    shell::{
+        self,
+        effective_shells,
+        is_posix_shell,
+    },
+};
+
+/// A SHA-256 digest identifying the content of a Dockerfile.
+///
+/// Its `Display` impl renders it the way Docker renders content digests, as `sha256:` followed by
+/// 64 lowercase hex digits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Digest([u8; 32]);
+
+impl Digest {
+    /// The raw bytes of this digest.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("sha256:")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Dockerfile {
+    /// Compute a stable SHA-256 digest of this Dockerfile's content.
+    ///
+    /// The digest is taken over a normalized rendering, so Dockerfiles which differ only in
+    /// formatting share a digest. Besides everything normalized by `fmt::format`, this covers
+    /// comments, line continuations and the whitespace between the words and operators of shell
+    /// commands run by a POSIX shell. The provenance of instructions plays no part in the digest.
+    pub fn digest(&self) -> Digest {
+        let normalized = format(self);
+        let shells = effective_shells(&normalized);
+        let normalized = Dockerfile::from_instructions(normalized.instructions().iter().zip(shells).map(|(instruction, shell)| {
+            match shell {
+                Some(shell) if !is_posix_shell(&shell) => instruction.clone(),
+                _ => normalize_shell(instruction),
+            }
+        }).collect());
+
+        let mut hasher = Sha256::new();
+        normalized.write_to_fmt(&mut hasher).expect("hashing never fails");
+        Digest(hasher.finish())
+    }
+}
+
+/// Render a shell-form command in the canonical form of its shell syntax tree, which collapses
+/// the whitespace between its words and operators and drops its comments.
+///
+/// Commands which cannot be parsed, or which contain newlines, such as heredocs, are left as they
+/// are. Commands run by a `SHELL` other than a POSIX shell must not be passed here, as their
+/// whitespace may be significant.
+fn normalize_shell(instruction: &Instruction) -> Instruction {
+    match instruction {
+        Instruction::Cmd(_) | Instruction::Entrypoint(_) | Instruction::Run(_) if !instruction.value().contains('\n') => (),
+        _ => return instruction.clone(),
+    }
+    let (flags, script) = match instruction.form() {
+        Some(Form::Shell(script)) => (instruction.value()[..instruction.value().len() - script.len()].trim_end(), script),
+        _ => return instruction.clone(),
+    };
+    let script = match shell::parse(script) {
+        Ok(script) => script.to_string(),
+        Err(_) => return instruction.clone(),
+    };
+    let value = if flags.is_empty() { script } else { format!("{} {}", flags, script) };
+    parser::instruction_for(instruction.keyword(), value).expect("every keyword other than a directive's is known")
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// SHA-256 ///////////////////////////////////////////////////////////////////////////////////////
+
+/// The SHA-256 round constants.
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The SHA-256 initial hash values.
+const H: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+/// A streaming SHA-256 hasher, as specified by FIPS 180-4.
+///
+/// It implements `fmt::Write` so that a Dockerfile can be rendered straight into it.
+///
+/// The crate has no dependencies, and hashing a Dockerfile is the only use it has for
+/// cryptography, so this small implementation is kept in place of the `sha2` crate and the
+/// dependencies it would bring to every user. It needs no more than a single, unkeyed hash and
+/// is checked against the NIST test vectors below, including messages spanning several blocks.
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Sha256{state: H, block: [0; 64], block_len: 0, total_len: 0}
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut out = [0; 32];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(&self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (word, chunk) in w.iter_mut().zip(self.block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+impl Write for Sha256 {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.update(s.as_bytes());
+        Ok(())
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sha256(input: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.write_str(input).unwrap();
+        Digest(hasher.finish()).to_string()
+    }
+
+    #[test]
+    fn sha256_matches_known_vectors() {
+        assert_eq!(sha256(""), "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256("abc"), "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            sha256("abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "sha256:248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        );
+        assert_eq!(
+            sha256("abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu"),
+            "sha256:cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1",
+        );
+        assert_eq!(sha256(&"a".repeat(1000)), "sha256:41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3");
+        assert_eq!(sha256(&"a".repeat(1_000_000)), "sha256:cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn sha256_pads_at_block_boundaries() {
+        // 55 bytes leave room for the padding in the same block, while 56 and 64 do not.
+        assert_eq!(sha256(&"a".repeat(55)), "sha256:9f4390f8d30c2dd92ec9f095b65e2b9ae9b0a925a5258e241c9f1e910f734318");
+        assert_eq!(sha256(&"a".repeat(56)), "sha256:b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a");
+        assert_eq!(sha256(&"a".repeat(64)), "sha256:ffe054fe7ae0cb6dc65c3af9b61d5209f439851db43d0ba5997337df154668eb");
+
+        let message = "a".repeat(64);
+        let mut hasher = Sha256::new();
+        for chunk in [&message[..3], &message[3..63], &message[63..]] {
+            hasher.write_str(chunk).unwrap();
+        }
+        assert_eq!(Digest(hasher.finish()).to_string(), sha256(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn digest_ignores_formatting() {
+        let tidy = "FROM rust:1.31 AS builder\nRUN apt-get update && apt-get install -y git\nENV A=1\nCMD [\"run\"]\n";
+        let messy = "# build stage\nfrom   rust:1.31   as builder\n\nrun apt-get update \\\n    &&   apt-get install -y git\nenv A 1\ncmd [ \"run\" ]\n";
+        let digest = Dockerfile::parse(tidy).unwrap().digest();
+        assert_eq!(Dockerfile::parse(messy).unwrap().digest(), digest);
+        assert_eq!(Dockerfile::base("rust:1.31 AS builder")
+            .push(crate::Run::new("apt-get update && apt-get install -y git"))
+            .push(crate::Env::new("A=1"))
+            .push(crate::Cmd::new("[\"run\"]"))
+            .finish()
+            .digest(), digest);
+    }
+
+    #[test]
+    fn digest_reflects_content() {
+        let digest = |input: &str| Dockerfile::parse(input).unwrap().digest();
+        assert_ne!(digest("FROM alpine\nRUN echo 'a  b'\n"), digest("FROM alpine\nRUN echo 'a b'\n"));
+        assert_ne!(digest("FROM alpine\nRUN echo a\n"), digest("FROM alpine\nCMD echo a\n"));
+        assert_ne!(digest("FROM alpine:3.18\n"), digest("FROM alpine:3.19\n"));
+    }
+
+    #[test]
+    fn digest_normalizes_shell_syntax() {
+        let digest = |input: &str| Dockerfile::parse(input).unwrap().digest();
+        assert_eq!(
+            digest("FROM alpine\nRUN if [ -f a ];then  make>log ;fi # build\n"),
+            digest("FROM alpine\nRUN if [ -f a ]; then make >log; fi\n"),
+        );
+        // The shell's escape character is a backslash, whatever the Dockerfile's.
+        assert_ne!(
+            digest("# escape=`\nFROM alpine\nRUN echo a\\  b\n"),
+            digest("# escape=`\nFROM alpine\nRUN echo a\\ b\n"),
+        );
+        assert_eq!(
+            digest("# escape=`\nFROM alpine\nRUN echo a\\ b  `\n  c\n"),
+            digest("# escape=`\nFROM alpine\nRUN echo a\\ b c\n"),
+        );
+    }
+
+    #[test]
+    fn digest_keeps_commands_of_other_shells() {
+        let digest = |input: &str| Dockerfile::parse(input).unwrap().digest();
+        let cmd = "FROM windows/servercore\nSHELL [\"cmd\", \"/S\", \"/C\"]\n";
+        assert_ne!(digest(&format!("{}RUN echo a   b> out.txt\n", cmd)), digest(&format!("{}RUN echo a b > out.txt\n", cmd)));
+        let pwsh = "FROM windows/servercore AS base\nSHELL [\"powershell\", \"-Command\"]\nFROM base\n";
+        assert_ne!(digest(&format!("{}RUN echo 'a'  ;  b\n", pwsh)), digest(&format!("{}RUN echo 'a'; b\n", pwsh)));
+        assert_eq!(
+            digest(&format!("{}SHELL [\"sh\", \"-c\"]\nRUN echo a   b\n", cmd)),
+            digest(&format!("{}SHELL [\"sh\", \"-c\"]\nRUN echo a b\n", cmd)),
+        );
+    }
+}
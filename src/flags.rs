@@ -0,0 +1,266 @@
+use std::{
+    error,
+    fmt,
+};
+
+/// The kind of value which an instruction flag accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlagKind {
+    /// A boolean flag, given as `--name`, `--name=true` or `--name=false`.
+    Bool,
+    /// A flag which requires a value, given as `--name=value`.
+    Value,
+}
+
+/// The declaration of a flag which an instruction accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlagSpec {
+    /// The name of the flag, without the leading `--`.
+    pub name: &'static str,
+    /// The kind of value this flag accepts.
+    pub kind: FlagKind,
+    /// Whether this flag may be given more than once.
+    pub repeatable: bool,
+}
+
+impl FlagSpec {
+    const fn value(name: &'static str) -> Self {
+        FlagSpec{name, kind: FlagKind::Value, repeatable: false}
+    }
+
+    const fn bool(name: &'static str) -> Self {
+        FlagSpec{name, kind: FlagKind::Bool, repeatable: false}
+    }
+
+    const fn repeated(name: &'static str) -> Self {
+        FlagSpec{name, kind: FlagKind::Value, repeatable: true}
+    }
+}
+
+/// The flags accepted by `ADD`.
+pub(crate) const ADD_FLAGS: &[FlagSpec] = &[
+    FlagSpec::value("chown"),
+    FlagSpec::value("chmod"),
+    FlagSpec::value("checksum"),
+    FlagSpec::bool("keep-git-dir"),
+    FlagSpec::bool("link"),
+    FlagSpec::repeated("exclude"),
+];
+
+/// The flags accepted by `COPY`.
+pub(crate) const COPY_FLAGS: &[FlagSpec] = &[
+    FlagSpec::value("from"),
+    FlagSpec::value("chown"),
+    FlagSpec::value("chmod"),
+    FlagSpec::bool("link"),
+    FlagSpec::bool("parents"),
+    FlagSpec::repeated("exclude"),
+];
+
+/// The flags accepted by `FROM`.
+pub(crate) const FROM_FLAGS: &[FlagSpec] = &[
+    FlagSpec::value("platform"),
+];
+
+/// The flags accepted by `HEALTHCHECK`.
+pub(crate) const HEALTHCHECK_FLAGS: &[FlagSpec] = &[
+    FlagSpec::value("interval"),
+    FlagSpec::value("timeout"),
+    FlagSpec::value("start-period"),
+    FlagSpec::value("start-interval"),
+    FlagSpec::value("retries"),
+];
+
+/// The flags accepted by `RUN`.
+pub(crate) const RUN_FLAGS: &[FlagSpec] = &[
+    FlagSpec::repeated("mount"),
+    FlagSpec::value("network"),
+    FlagSpec::value("security"),
+];
+
+/// A single `--name` or `--name=value` flag given to an instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Flag {
+    name: String,
+    value: Option<String>,
+}
+
+impl Flag {
+    /// The name of this flag, without the leading `--`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value of this flag, with any quoting removed, if one was given.
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+}
+
+/// The flags given to an instruction, in the order in which they appeared.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Flags(Vec<Flag>);
+
+impl Flags {
+    /// Split the leading flags off of an instruction's arguments.
+    ///
+    /// Flags are the leading words beginning with `--`. Quotes within a flag word are removed,
+    /// and whitespace inside of quotes does not end the word. A bare `--` ends the flags and is
+    /// consumed. Returns the flags along with the remaining arguments.
+    pub fn parse(args: &str) -> Result<(Flags, &str), FlagError> {
+        let mut flags = vec![];
+        let mut rest = args.trim_start();
+        while rest.starts_with("--") {
+            let (word, remainder) = split_word(rest)?;
+            rest = remainder.trim_start();
+            if word == "--" {
+                break;
+            }
+            let body = &word[2..];
+            let (name, value) = match body.find('=') {
+                Some(idx) => (&body[..idx], Some(body[idx + 1..].to_string())),
+                None => (body, None),
+            };
+            let well_formed = !name.is_empty()
+                && !name.starts_with('-')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !well_formed {
+                return Err(FlagError::Malformed(word));
+            }
+            flags.push(Flag{name: name.to_string(), value});
+        }
+        Ok((Flags(flags), rest))
+    }
+
+    /// Check these flags against the flags an instruction accepts.
+    ///
+    /// This reproduces the errors Docker reports for unknown flags, duplicated flags and flags
+    /// with missing or invalid values.
+    pub fn check(&self, accepted: &[FlagSpec]) -> Result<(), FlagError> {
+        for (idx, flag) in self.0.iter().enumerate() {
+            let spec = match accepted.iter().find(|spec| spec.name == flag.name) {
+                Some(spec) => spec,
+                None => return Err(FlagError::Unknown(flag.name.clone())),
+            };
+            if !spec.repeatable && self.0[..idx].iter().any(|prev| prev.name == flag.name) {
+                return Err(FlagError::Duplicate(flag.name.clone()));
+            }
+            match (spec.kind, flag.value()) {
+                (FlagKind::Value, None) => return Err(FlagError::MissingValue(flag.name.clone())),
+                (FlagKind::Bool, Some(value)) if value != "true" && value != "false" => {
+                    return Err(FlagError::InvalidBool{name: flag.name.clone(), value: value.to_string()});
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// An iterator over all of the flags.
+    pub fn iter(&self) -> impl Iterator<Item=&Flag> {
+        self.0.iter()
+    }
+
+    /// Whether no flags were given.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the value of the first flag with the given name.
+    ///
+    /// Boolean flags given without a value yield `Some("")`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter().find(|flag| flag.name == name).map(|flag| flag.value().unwrap_or(""))
+    }
+
+    /// Get the values of every flag with the given name, for repeatable flags like `--mount`.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.0.iter()
+            .filter(move |flag| flag.name == name)
+            .map(|flag| flag.value().unwrap_or(""))
+    }
+}
+
+/// Split a single flag word off of the front of the input, removing any quotes.
+fn split_word(input: &str) -> Result<(String, &str), FlagError> {
+    let mut word = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = input.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => return Ok((word, &input[idx..])),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.push(c),
+            (_, '\\') => match chars.next() {
+                Some((_, escaped)) => word.push(escaped),
+                None => word.push('\\'),
+            },
+            (_, c) => word.push(c),
+        }
+    }
+    match quote {
+        Some(_) => Err(FlagError::Malformed(input.to_string())),
+        None => Ok((word, "")),
+    }
+}
+
+/// An error found in the flags given to an instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FlagError {
+    /// A flag did not take the form `--name` or `--name=value`, or had an unterminated quote.
+    Malformed(String),
+    /// A flag which the instruction does not accept.
+    Unknown(String),
+    /// A flag which may only be given once was given more than once.
+    Duplicate(String),
+    /// A flag which requires a value was given without one.
+    MissingValue(String),
+    /// A boolean flag was given a value other than `true` or `false`.
+    InvalidBool {
+        name: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for FlagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlagError::Malformed(flag) => write!(f, "malformed flag: {}", flag),
+            FlagError::Unknown(name) => write!(f, "unknown flag: {}", name),
+            FlagError::Duplicate(name) => write!(f, "duplicate flag specified: {}", name),
+            FlagError::MissingValue(name) => write!(f, "missing a value on flag: {}", name),
+            FlagError::InvalidBool{name, value} => write!(f, "expecting boolean value for flag {}, not: {}", name, value),
+        }
+    }
+}
+
+impl error::Error for FlagError {}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags_parse_with_quoting() {
+        let (flags, rest) = Flags::parse(r#"--mount=type=secret,id="my id" --mount=type=cache --link -- --not-a-flag"#).unwrap();
+        assert_eq!(flags.get_all("mount").collect::<Vec<_>>(), vec!["type=secret,id=my id", "type=cache"]);
+        assert_eq!(flags.get("link"), Some(""));
+        assert_eq!(rest, "--not-a-flag");
+        assert_eq!(flags.check(RUN_FLAGS), Err(FlagError::Unknown("link".into())));
+    }
+
+    #[test]
+    fn flags_check_against_specs() {
+        let check = |args| Flags::parse(args).unwrap().0.check(COPY_FLAGS);
+        assert_eq!(check("--from=builder --link=true --exclude=a --exclude=b /out /app"), Ok(()));
+        assert_eq!(check("--from=a --from=b /out /app"), Err(FlagError::Duplicate("from".into())));
+        assert_eq!(check("--from /out /app"), Err(FlagError::MissingValue("from".into())));
+        assert_eq!(check("--link=yes /out /app"), Err(FlagError::InvalidBool{name: "link".into(), value: "yes".into()}));
+        assert_eq!(Flags::parse("--=x /out"), Err(FlagError::Malformed("--=x".into())));
+        assert_eq!(Flags::parse("--chown='a b /out"), Err(FlagError::Malformed("--chown='a b /out".into())));
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{
+    builder::Dockerfile,
+    flags::{
+        FlagKind,
+        FlagSpec,
+        Flags,
+    },
+    form::Form,
+    instructions::Instruction,
+    json,
+    parser::{
+        self,
+        ParseError,
+    },
+    render::{
+        self,
+        split_words,
+    },
+};
+
+/// Format a Dockerfile into its canonical form.
+///
+/// Formatting normalizes the casing of keywords, the order and quoting of flags, the spacing
+/// between arguments, the `key=value` style of `ENV` and `LABEL`, and the spacing of JSON arrays.
+/// The canonical form has exactly the same meaning to Docker as the original, and formatting is
+/// idempotent: formatting an already formatted Dockerfile changes nothing. Instructions which
+/// Docker would reject, such as those with unknown flags, are left untouched.
+pub fn format(dockerfile: &Dockerfile) -> Dockerfile {
+    let escape = render::escape_char(dockerfile);
+    Dockerfile{
+        instructions: dockerfile.instructions().iter().map(|inst| format_instruction(inst, escape)).collect(),
+        provenance: dockerfile.provenance.clone(),
+        lines: dockerfile.lines.clone(),
+    }
+}
+
+/// Parse the given text as a Dockerfile and render it in its canonical form.
+pub fn format_str(input: &str) -> Result<String, ParseError> {
+    Ok(format(&Dockerfile::parse(input)?).to_string())
+}
+
+/// Format a single instruction into its canonical form.
+fn format_instruction(instruction: &Instruction, escape: char) -> Instruction {
+    if let Instruction::Directive(_) = instruction {
+        return instruction.clone();
+    }
+    let (flags, args) = match Flags::parse(instruction.value().trim()) {
+        Ok((flags, args)) if flags.check(instruction.accepted_flags()).is_ok() => (flags, args),
+        _ => return instruction.clone(),
+    };
+
+    let args = match instruction {
+        Instruction::Add(_) | Instruction::Copy(_) | Instruction::Volume(_) => format_form(args, true),
+        Instruction::Cmd(_) | Instruction::Entrypoint(_) | Instruction::Run(_) | Instruction::Shell(_) => format_form(args, false),
+        Instruction::Env(_) | Instruction::Label(_) => format_pairs(args, escape),
+        Instruction::Expose(_) => args.split_whitespace().collect::<Vec<_>>().join(" "),
+        Instruction::From(_) => format_from(args),
+        Instruction::Healthcheck(_) => format_healthcheck(args),
+        Instruction::Onbuild(_) => format_onbuild(args, escape),
+        _ => args.to_string(),
+    };
+
+    let flags = format_flags(&flags, instruction.accepted_flags());
+    let value = match (flags.is_empty(), args.is_empty()) {
+        (true, _) => args,
+        (false, true) => flags,
+        (false, false) => format!("{} {}", flags, args),
+    };
+    parser::instruction_for(instruction.keyword(), value).expect("every keyword other than a directive's is known")
+}
+
+/// Format arguments which may be a JSON array.
+///
+/// JSON arrays are rendered as `["a", "b"]`. Plain text arguments which Docker splits on
+/// whitespace have their whitespace collapsed, while those passed to a shell are left as they are.
+fn format_form(args: &str, whitespace_delimited: bool) -> String {
+    match Form::detect(args) {
+        Some(Form::Json(elems)) => {
+            let elems: Vec<_> = elems.iter().map(|elem| json::quote(elem)).collect();
+            format!("[{}]", elems.join(", "))
+        }
+        Some(Form::Shell(text)) if whitespace_delimited => text.split_whitespace().collect::<Vec<_>>().join(" "),
+        _ => args.to_string(),
+    }
+}
+
+/// Format the arguments of `ENV` or `LABEL` into the `key=value` style.
+///
+/// The legacy `key value` style takes the rest of the line verbatim as the value, which is
+/// quoted as needed to keep it a single word.
+fn format_pairs(args: &str, escape: char) -> String {
+    let words = split_words(args, escape);
+    let key = match words.first() {
+        Some((_, key)) => *key,
+        None => return args.to_string(),
+    };
+    if key.contains('=') {
+        if words.iter().any(|(_, word)| !word.contains('=')) {
+            return args.to_string();
+        }
+        return words.iter().map(|(_, word)| *word).collect::<Vec<_>>().join(" ");
+    }
+
+    let value = args[key.len()..].trim();
+    if value.is_empty() {
+        return args.to_string();
+    }
+    if split_words(value, escape).len() == 1 {
+        return format!("{}={}", key, value);
+    }
+    if !value.contains(['"', '\'', escape]) {
+        return format!("{}=\"{}\"", key, value);
+    }
+
+    // Escape any whitespace outside of quotes, leaving everything else exactly as it was.
+    let mut quoted = String::with_capacity(value.len());
+    let mut offset = 0;
+    for (space, word) in split_words(value, escape) {
+        for c in space.chars() {
+            quoted.push(escape);
+            quoted.push(c);
+        }
+        quoted.push_str(word);
+        offset += space.len() + word.len();
+    }
+    quoted.push_str(&value[offset..]);
+    format!("{}={}", key, quoted)
+}
+
+/// Format the arguments of `FROM`, uppercasing the `AS` keyword.
+fn format_from(args: &str) -> String {
+    let words: Vec<_> = args.split_whitespace().collect();
+    match words.as_slice() {
+        [image, as_, name] if as_.eq_ignore_ascii_case("as") => format!("{} AS {}", image, name),
+        _ => words.join(" "),
+    }
+}
+
+/// Format the arguments of `HEALTHCHECK`, uppercasing the nested `CMD` or `NONE`.
+fn format_healthcheck(args: &str) -> String {
+    let (kind, rest) = args.split_at(args.find(char::is_whitespace).unwrap_or(args.len()));
+    match kind.to_ascii_uppercase().as_str() {
+        "NONE" if rest.trim().is_empty() => "NONE".to_string(),
+        "CMD" if !rest.trim().is_empty() => format!("CMD {}", format_form(rest.trim(), false)),
+        _ => args.to_string(),
+    }
+}
+
+/// Format the instruction nested within an `ONBUILD`.
+fn format_onbuild(args: &str, escape: char) -> String {
+    match args.parse::<Instruction>() {
+        Ok(Instruction::Directive(_)) | Err(_) => args.to_string(),
+        Ok(nested) => {
+            let nested = format_instruction(&nested, escape);
+            format!("{} {}", nested.keyword(), nested.value())
+        }
+    }
+}
+
+/// Format flags in the order in which the instruction declares them, quoting values as needed.
+fn format_flags(flags: &Flags, accepted: &[FlagSpec]) -> String {
+    let position = |name: &str| accepted.iter().position(|spec| spec.name == name);
+    let mut flags: Vec<_> = flags.iter().collect();
+    flags.sort_by_key(|flag| position(flag.name()));
+
+    let formatted: Vec<_> = flags.into_iter().map(|flag| {
+        let kind = position(flag.name()).map(|idx| accepted[idx].kind);
+        match (kind, flag.value()) {
+            (Some(FlagKind::Bool), None) | (Some(FlagKind::Bool), Some("true")) => format!("--{}", flag.name()),
+            (_, None) => format!("--{}", flag.name()),
+            (_, Some(value)) => format_flag(flag.name(), value),
+        }
+    }).collect();
+    formatted.join(" ")
+}
+
+/// Format a single `--name=value` flag, quoting the value if it needs to be.
+pub(crate) fn format_flag(name: &str, value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+        format!("--{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        format!("--{}={}", name, value)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MESSY: &str = r##"# syntax=docker/dockerfile:1
+from   --platform=$BUILDPLATFORM   rust:1.31   as   builder
+copy --link=true --chown=app   --from=base ["src",   "/app/src"]
+add   --exclude=*.md   a.txt    b.txt   /dest/
+run --network=none   --mount=type=secret,id="my id" --mount=type=cache,target=/cache echo  "hello   world"
+cmd [ "run", "--release" ]
+env   GREETING   hello   there
+env   A=1   B="two   words"
+label   description say "hi  there"
+expose   80   443/udp
+healthcheck --timeout=3s   --interval=5s cmd ["curl","-f","http://localhost/"]
+onbuild   copy   --chown=app   ["a",   "b"]
+"##;
+
+    const CANONICAL: &str = r##"# syntax=docker/dockerfile:1
+FROM --platform=$BUILDPLATFORM rust:1.31 AS builder
+COPY --from=base --chown=app --link ["src", "/app/src"]
+ADD --exclude=*.md a.txt b.txt /dest/
+RUN --mount="type=secret,id=my id" --mount=type=cache,target=/cache --network=none echo  "hello   world"
+CMD ["run", "--release"]
+ENV GREETING="hello   there"
+ENV A=1 B="two   words"
+LABEL description=say\ "hi  there"
+EXPOSE 80 443/udp
+HEALTHCHECK --interval=5s --timeout=3s CMD ["curl", "-f", "http://localhost/"]
+ONBUILD COPY --chown=app ["a", "b"]
+"##;
+
+    #[test]
+    fn format_produces_canonical_output() {
+        assert_eq!(format_str(MESSY).unwrap(), CANONICAL);
+    }
+
+    #[test]
+    fn format_is_idempotent() {
+        assert_eq!(format_str(CANONICAL).unwrap(), CANONICAL);
+    }
+
+    /// The arguments of an instruction as Docker sees them, and whether they were JSON.
+    fn arguments(instruction: &Instruction) -> Option<(bool, Vec<String>)> {
+        match (instruction, instruction.form()?) {
+            (_, Form::Json(elems)) => Some((true, elems)),
+            (Instruction::Add(_), Form::Shell(text)) | (Instruction::Copy(_), Form::Shell(text)) | (Instruction::Volume(_), Form::Shell(text)) => {
+                Some((false, text.split_whitespace().map(str::to_string).collect()))
+            }
+            (_, Form::Shell(text)) => Some((false, vec![text.to_string()])),
+        }
+    }
+
+    #[test]
+    fn format_preserves_meaning() {
+        let original = Dockerfile::parse(MESSY).unwrap();
+        let formatted = format(&original);
+        for (before, after) in original.instructions().iter().zip(formatted.instructions()) {
+            assert_eq!(before.keyword(), after.keyword());
+            assert_eq!(arguments(before), arguments(after));
+            let (mut before_flags, mut after_flags): (Vec<_>, Vec<_>) = (
+                before.flags().unwrap().iter().map(|f| (f.name().to_string(), f.value().map(str::to_string))).collect(),
+                after.flags().unwrap().iter().map(|f| (f.name().to_string(), f.value().map(str::to_string))).collect(),
+            );
+            before_flags.sort();
+            after_flags.sort();
+            for flags in [&mut before_flags, &mut after_flags] {
+                for (_, value) in flags.iter_mut() {
+                    if value.as_deref() == Some("true") {
+                        *value = None;
+                    }
+                }
+            }
+            assert_eq!(before_flags, after_flags);
+        }
+    }
+
+    #[test]
+    fn format_leaves_invalid_instructions_alone() {
+        let input = "FROM alpine\nCOPY   --from=a   --bogus   a   b\n";
+        let (dockerfile, _) = Dockerfile::parse_tolerant(input);
+        assert_eq!(format(&dockerfile).to_string(), "FROM alpine\nCOPY --from=a   --bogus   a   b\n");
+    }
+}
@@ -0,0 +1,45 @@
+use crate::json::{
+    self,
+    JsonError,
+};
+
+/// The form in which the arguments of an instruction were written.
+///
+/// `ADD`, `CMD`, `COPY`, `ENTRYPOINT`, `RUN`, `SHELL` and `VOLUME` accept their arguments either
+/// as a JSON array of strings, or as plain text. Docker decides between the two by attempting to
+/// decode the arguments as JSON, falling back to plain text whenever that fails, so something
+/// like `CMD ["a", 'b']` is treated as a shell command. This type reproduces that decision.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Form<'a> {
+    /// A JSON array of strings, also known as the exec form.
+    Json(Vec<String>),
+
+    /// Plain text. `CMD`, `ENTRYPOINT` and `RUN` pass it to the shell, while `ADD`, `COPY` and
+    /// `VOLUME` split it on whitespace.
+    Shell(&'a str),
+}
+
+impl<'a> Form<'a> {
+    /// Determine the form of the given arguments, which must not include any leading flags.
+    ///
+    /// Returns `None` for a valid JSON array containing anything other than strings, which
+    /// Docker rejects rather than falling back to plain text.
+    pub fn detect(args: &'a str) -> Option<Form<'a>> {
+        match json::parse_string_array(args) {
+            Ok(elems) => Some(Form::Json(elems)),
+            Err(JsonError::NotStrings) => None,
+            Err(JsonError::Invalid) => Some(Form::Shell(args)),
+        }
+    }
+
+    /// Whether these arguments are plain text which begins like a JSON array.
+    ///
+    /// This almost always indicates a mistake, such as single quotes or a trailing comma within
+    /// what was meant to be a JSON array.
+    pub fn fell_back_from_json(&self) -> bool {
+        match self {
+            Form::Shell(text) => text.trim_start().starts_with('['),
+            Form::Json(_) => false,
+        }
+    }
+}
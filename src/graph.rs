@@ -0,0 +1,294 @@
+use crate::{
+    builder::Dockerfile,
+    instructions::Instruction,
+    render,
+    stage::{
+        self,
+        EdgeKind,
+    },
+};
+
+/// The dependencies between the stages of a Dockerfile.
+///
+/// Stages are identified by their index, as given by `Stage::index`. Only references to earlier
+/// stages become edges, as Docker rejects all others, so the graph is always acyclic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageGraph {
+    stages: usize,
+    edges: Vec<StageEdge>,
+}
+
+/// A dependency of one stage upon an earlier one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StageEdge {
+    /// The stage which has the dependency.
+    pub stage: usize,
+
+    /// The stage depended upon.
+    pub dependency: usize,
+
+    /// The kind of reference which created the dependency.
+    pub kind: EdgeKind,
+}
+
+impl StageGraph {
+    /// The number of stages in the graph.
+    pub fn len(&self) -> usize {
+        self.stages
+    }
+
+    /// Whether the graph has no stages.
+    pub fn is_empty(&self) -> bool {
+        self.stages == 0
+    }
+
+    /// Every edge of the graph, in the order in which the references appear.
+    pub fn edges(&self) -> &[StageEdge] {
+        &self.edges
+    }
+
+    /// The stages which the given stage depends upon directly, in ascending order.
+    pub fn dependencies(&self, stage: usize) -> Vec<usize> {
+        let mut deps: Vec<_> = self.edges.iter().filter(|edge| edge.stage == stage).map(|edge| edge.dependency).collect();
+        deps.sort_unstable();
+        deps.dedup();
+        deps
+    }
+
+    /// The stages which depend upon the given stage directly, in ascending order.
+    pub fn dependents(&self, stage: usize) -> Vec<usize> {
+        let mut deps: Vec<_> = self.edges.iter().filter(|edge| edge.dependency == stage).map(|edge| edge.stage).collect();
+        deps.sort_unstable();
+        deps.dedup();
+        deps
+    }
+
+    /// The stages needed to build the given stage, including itself, in ascending order.
+    pub fn required_by(&self, target: usize) -> Vec<usize> {
+        let mut required = vec![false; self.stages];
+        if target >= self.stages {
+            return vec![];
+        }
+        required[target] = true;
+        // Every edge points to an earlier stage, so a single pass in reverse order suffices.
+        for stage in (0..=target).rev() {
+            if required[stage] {
+                for dep in self.dependencies(stage) {
+                    required[dep] = true;
+                }
+            }
+        }
+        (0..self.stages).filter(|&stage| required[stage]).collect()
+    }
+
+    /// Group the stages into levels which can be built concurrently.
+    ///
+    /// Every stage is placed in the level after the last of its dependencies, so the stages of
+    /// each level depend only upon those of earlier levels. Stages within a level are in
+    /// ascending order.
+    pub fn levels(&self) -> Vec<Vec<usize>> {
+        let mut level_of = vec![0; self.stages];
+        let mut levels: Vec<Vec<usize>> = vec![];
+        // Dependencies always come first, so every level is known by the time it is needed.
+        for stage in 0..self.stages {
+            let level = self.dependencies(stage).into_iter().map(|dep| level_of[dep] + 1).max().unwrap_or(0);
+            level_of[stage] = level;
+            if levels.len() <= level {
+                levels.resize_with(level + 1, Vec::new);
+            }
+            levels[level].push(stage);
+        }
+        levels
+    }
+
+    /// The longest chain of dependent stages, which bounds how quickly the graph can be built
+    /// however many stages are built concurrently.
+    ///
+    /// Every stage counts equally. See `critical_path_by` to weigh stages by their cost to build.
+    pub fn critical_path(&self) -> Vec<usize> {
+        self.critical_path_by(|_| 1)
+    }
+
+    /// The chain of dependent stages with the greatest total weight, from its first stage to its
+    /// last.
+    ///
+    /// Ties are broken in favour of earlier stages.
+    pub fn critical_path_by<F: Fn(usize) -> u64>(&self, weight: F) -> Vec<usize> {
+        let mut cost = vec![0; self.stages];
+        let mut previous = vec![None; self.stages];
+        for stage in 0..self.stages {
+            let mut best: Option<usize> = None;
+            for dep in self.dependencies(stage) {
+                if best.is_none_or(|best| cost[dep] > cost[best]) {
+                    best = Some(dep);
+                }
+            }
+            cost[stage] = best.map_or(0, |best| cost[best]) + weight(stage);
+            previous[stage] = best;
+        }
+
+        let mut end = match (0..self.stages).rev().max_by_key(|&stage| cost[stage]) {
+            Some(end) => end,
+            None => return vec![],
+        };
+        let mut path = vec![end];
+        while let Some(prev) = previous[end] {
+            path.push(prev);
+            end = prev;
+        }
+        path.reverse();
+        path
+    }
+}
+
+impl Dockerfile {
+    /// Build the graph of dependencies between the stages of this Dockerfile.
+    ///
+    /// Edges come from `FROM <stage>`, `COPY --from=<stage>` and `RUN --mount=from=<stage>`.
+    pub fn stage_graph(&self) -> StageGraph {
+        let stages = self.stages();
+        let mut edges = vec![];
+        for current in &stages {
+            for instruction in &self.instructions()[current.range()] {
+                for (kind, reference) in stage::stage_references(instruction) {
+                    if let Some(Ok(dependency)) = stage::resolve(&stages, &reference, kind) {
+                        if dependency < current.index() {
+                            edges.push(StageEdge{stage: current.index(), dependency, kind});
+                        }
+                    }
+                }
+            }
+        }
+        StageGraph{stages: stages.len(), edges}
+    }
+
+    /// Produce a minimal Dockerfile containing only the stages needed to build the named target.
+    ///
+    /// This follows BuildKit, which builds only the target stage and the stages it depends upon.
+    /// Everything before the first `FROM` is kept, stages keep their order, and numeric
+    /// `--from` references are renumbered to match. Returns `None` if no stage has the given name.
+    pub fn prune_to_target(&self, target: &str) -> Option<Dockerfile> {
+        let stages = self.stages();
+        let target = stages.iter().find(|stage| stage.name().is_some_and(|name| name.eq_ignore_ascii_case(target)))?;
+        let required = self.stage_graph().required_by(target.index());
+
+        let mut renumbered = vec![None; stages.len()];
+        for (new, &old) in required.iter().enumerate() {
+            renumbered[old] = Some(new);
+        }
+        let escape = render::escape_char(self);
+        let preamble = stages.first().map_or(self.instructions().len(), |stage| stage.range().start);
+
+        let mut instructions = vec![];
+        let mut provenance = vec![];
+        let mut lines = vec![];
+        for idx in (0..preamble).chain(required.iter().flat_map(|&stage| stages[stage].range())) {
+            instructions.push(renumber(&self.instructions()[idx], &renumbered, escape));
+            provenance.push(self.provenance(idx));
+            lines.push(self.source_line(idx));
+        }
+        Some(Dockerfile{instructions, provenance, lines})
+    }
+}
+
+/// Rewrite the numeric stage references of `COPY --from` and `RUN --mount=from=` according to
+/// the given map from old stage indices to new.
+fn renumber(instruction: &Instruction, renumbered: &[Option<usize>], escape: char) -> Instruction {
+    stage::rewrite_references(instruction, escape, |kind, reference| match kind {
+        EdgeKind::From => None,
+        EdgeKind::Copy | EdgeKind::Mount => reference.parse::<usize>().ok()
+            .and_then(|old| renumbered.get(old).copied().flatten())
+            .map(|new| new.to_string()),
+    })
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MULTI_STAGE: &str = r##"# syntax=docker/dockerfile:1
+ARG RUST_VERSION=1.75
+FROM rust:${RUST_VERSION} AS base
+RUN cargo install cargo-chef
+FROM base AS planner
+COPY . .
+RUN cargo chef prepare
+FROM node:20 AS assets
+RUN npm run build
+FROM base AS builder
+COPY --from=planner /recipe.json recipe.json
+RUN --mount=type=cache,target=/cache,from=assets cargo build --release
+FROM alpine AS test
+COPY --from=3 /app /app
+FROM debian:stable-slim AS release
+COPY --from=3 /app/target/release/app /usr/local/bin/app
+"##;
+
+    #[test]
+    fn stage_graph_edges() {
+        let graph = Dockerfile::parse(MULTI_STAGE).unwrap().stage_graph();
+        assert_eq!(graph.len(), 6);
+        assert_eq!(graph.edges(), &[
+            StageEdge{stage: 1, dependency: 0, kind: EdgeKind::From},
+            StageEdge{stage: 3, dependency: 0, kind: EdgeKind::From},
+            StageEdge{stage: 3, dependency: 1, kind: EdgeKind::Copy},
+            StageEdge{stage: 3, dependency: 2, kind: EdgeKind::Mount},
+            StageEdge{stage: 4, dependency: 3, kind: EdgeKind::Copy},
+            StageEdge{stage: 5, dependency: 3, kind: EdgeKind::Copy},
+        ]);
+        assert_eq!(graph.dependencies(3), vec![0, 1, 2]);
+        assert_eq!(graph.dependents(3), vec![4, 5]);
+        assert_eq!(graph.required_by(5), vec![0, 1, 2, 3, 5]);
+        assert_eq!(graph.required_by(2), vec![2]);
+    }
+
+    #[test]
+    fn prune_to_target_keeps_only_needed_stages() {
+        let dockerfile = Dockerfile::parse(MULTI_STAGE).unwrap();
+        let pruned = dockerfile.prune_to_target("Release").unwrap();
+        assert_eq!(pruned.to_string(), r##"# syntax=docker/dockerfile:1
+ARG RUST_VERSION=1.75
+FROM rust:${RUST_VERSION} AS base
+RUN cargo install cargo-chef
+FROM base AS planner
+COPY . .
+RUN cargo chef prepare
+FROM node:20 AS assets
+RUN npm run build
+FROM base AS builder
+COPY --from=planner /recipe.json recipe.json
+RUN --mount=type=cache,target=/cache,from=assets cargo build --release
+FROM debian:stable-slim AS release
+COPY --from=3 /app/target/release/app /usr/local/bin/app
+"##);
+        assert_eq!(pruned.check_stage_refs(), Ok(()));
+
+        let pruned = dockerfile.prune_to_target("assets").unwrap();
+        assert_eq!(pruned.to_string(), "# syntax=docker/dockerfile:1\nARG RUST_VERSION=1.75\nFROM node:20 AS assets\nRUN npm run build\n");
+        assert!(dockerfile.prune_to_target("missing").is_none());
+    }
+
+    #[test]
+    fn prune_to_target_renumbers_references() {
+        let dockerfile = Dockerfile::parse("FROM alpine AS unused\nFROM alpine\nRUN make\nFROM alpine AS out\nCOPY --from=1 /a /a\nRUN --mount=from=1,target=/b ls\n").unwrap();
+        assert_eq!(
+            dockerfile.prune_to_target("out").unwrap().to_string(),
+            "FROM alpine\nRUN make\nFROM alpine AS out\nCOPY --from=0 /a /a\nRUN --mount=from=0,target=/b ls\n",
+        );
+    }
+
+    #[test]
+    fn levels_and_critical_path() {
+        let graph = Dockerfile::parse(MULTI_STAGE).unwrap().stage_graph();
+        assert_eq!(graph.levels(), vec![vec![0, 2], vec![1], vec![3], vec![4, 5]]);
+        assert_eq!(graph.critical_path(), vec![0, 1, 3, 4]);
+        assert_eq!(graph.critical_path_by(|stage| if stage == 2 { 10 } else { 1 }), vec![2, 3, 4]);
+
+        let empty = Dockerfile::parse("ARG A=1\n").unwrap().stage_graph();
+        assert!(empty.levels().is_empty());
+        assert!(empty.critical_path().is_empty());
+    }
+}
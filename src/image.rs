@@ -0,0 +1,62 @@
+/// A reference to an image, as given to `FROM`, in the form `[registry/]name[:tag][@digest]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageRef<'a> {
+    /// The name of the image, including any registry, such as `docker.io/library/alpine`.
+    pub name: &'a str,
+
+    /// The tag of the image, such as `3.19`.
+    pub tag: Option<&'a str>,
+
+    /// The digest of the image, such as `sha256:4bcff6...`.
+    pub digest: Option<&'a str>,
+}
+
+impl<'a> ImageRef<'a> {
+    /// Split an image reference into its parts.
+    ///
+    /// A colon is only taken to begin the tag when it follows the last `/`, so that the port of a
+    /// registry, as in `localhost:5000/app`, is part of the name.
+    pub fn parse(reference: &'a str) -> Self {
+        let (rest, digest) = match reference.find('@') {
+            Some(idx) => (&reference[..idx], Some(&reference[idx + 1..])),
+            None => (reference, None),
+        };
+        let last_component = rest.rfind('/').map_or(0, |idx| idx + 1);
+        match rest[last_component..].find(':') {
+            Some(idx) => ImageRef{name: &rest[..last_component + idx], tag: Some(&rest[last_component + idx + 1..]), digest},
+            None => ImageRef{name: rest, tag: None, digest},
+        }
+    }
+
+    /// Whether this is the reserved `scratch` image, which is empty rather than pulled.
+    pub fn is_scratch(&self) -> bool {
+        self.name.eq_ignore_ascii_case("scratch") && self.tag.is_none() && self.digest.is_none()
+    }
+
+    /// Whether any part of this reference interpolates a build argument, in which case it cannot
+    /// be known until build time.
+    pub fn is_interpolated(&self) -> bool {
+        self.name.contains('$') || self.tag.is_some_and(|tag| tag.contains('$')) || self.digest.is_some_and(|digest| digest.contains('$'))
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn image_ref_parse() {
+        assert_eq!(ImageRef::parse("alpine"), ImageRef{name: "alpine", tag: None, digest: None});
+        assert_eq!(ImageRef::parse("localhost:5000/team/app:1.2"), ImageRef{name: "localhost:5000/team/app", tag: Some("1.2"), digest: None});
+        assert_eq!(
+            ImageRef::parse("rust:1.75@sha256:abc"),
+            ImageRef{name: "rust", tag: Some("1.75"), digest: Some("sha256:abc")},
+        );
+        assert_eq!(ImageRef::parse("ghcr.io/a/b@sha256:abc"), ImageRef{name: "ghcr.io/a/b", tag: None, digest: Some("sha256:abc")});
+        assert!(ImageRef::parse("SCRATCH").is_scratch());
+        assert!(ImageRef::parse("rust:${RUST_VERSION}").is_interpolated());
+    }
+}
@@ -2,12 +2,54 @@ use std::{
     borrow::Cow,
     convert,
     fmt,
+    str::FromStr,
 };
 
+use crate::{
+    flags::{
+        self,
+        FlagError,
+        FlagSpec,
+        Flags,
+    },
+    form::Form,
+    image::ImageRef,
+    parser::{
+        self,
+        ExpectedInstruction,
+        ParseError,
+    },
+    stage::{
+        self,
+        StageRef,
+    },
+};
+
+/// Implement `Debug`, `PartialEq` and `Eq` for an instruction which may remember a `StageRef`,
+/// considering only its text.
+macro_rules! impl_ignoring_stage_ref {
+    ($name:ident) => {
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_tuple(stringify!($name)).field(&self.0).finish()
+            }
+        }
+
+        impl PartialEq for $name {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        impl Eq for $name {}
+    };
+}
+
 /// The `ADD` instruction copies new files, directories or remote file URLs from `<src>` and adds
 /// them to the filesystem of the image at the path `<dest>`.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#add).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Add(Cow<'static, str>);
 
 impl Add {
@@ -18,7 +60,7 @@ impl Add {
 
 impl fmt::Display for Add {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ADD {}\n", &self.0)
+        writeln!(f, "ADD {}", &self.0)
     }
 }
 
@@ -26,23 +68,30 @@ impl fmt::Display for Add {
 /// the `docker build` command using the `--build-arg <varname>=<value>` flag.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#arg).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Arg(Cow<'static, str>);
 
 impl Arg {
     pub fn new<T: Into<Cow<'static, str>>>(val: T) -> Self {
         Arg(val.into())
     }
+
+    /// The raw arguments of this `ARG`.
+    pub(crate) fn value(&self) -> &str {
+        &self.0
+    }
 }
 
 impl fmt::Display for Arg {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ARG {}\n", &self.0)
+        writeln!(f, "ARG {}", &self.0)
     }
 }
 
 /// The main purpose of a `CMD` is to provide defaults for an executing container.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#cmd).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Cmd(Cow<'static, str>);
 
 impl Cmd {
@@ -53,7 +102,7 @@ impl Cmd {
 
 impl fmt::Display for Cmd {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "CMD {}\n", &self.0)
+        writeln!(f, "CMD {}", &self.0)
     }
 }
 
@@ -61,24 +110,43 @@ impl fmt::Display for Cmd {
 /// filesystem of the container at the path `<dest>`.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#copy).
-pub struct Copy(Cow<'static, str>);
+///
+/// A `COPY` made with `Copy::from_stage` remembers the stage it was made from, so that
+/// `Dockerfile::check_stage_refs` can check that the stage is in the same Dockerfile. This plays
+/// no part in comparisons.
+#[derive(Clone)]
+pub struct Copy(Cow<'static, str>, Option<StageRef>);
 
 impl Copy {
     pub fn new<T: Into<Cow<'static, str>>>(val: T) -> Self {
-        Copy(val.into())
+        Copy(val.into(), None)
+    }
+
+    /// Create a `COPY` instruction which copies `args`, the sources and destination, from the
+    /// given stage.
+    pub fn from_stage<T: AsRef<str>>(stage: &StageRef, args: T) -> Self {
+        Copy(format!("--from={} {}", stage.name(), args.as_ref()).into(), Some(stage.clone()))
+    }
+
+    /// The stage this instruction was made from with `Copy::from_stage`, if any.
+    pub(crate) fn stage_ref(&self) -> Option<&StageRef> {
+        self.1.as_ref()
     }
 }
 
 impl fmt::Display for Copy {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "COPY {}\n", &self.0)
+        writeln!(f, "COPY {}", &self.0)
     }
 }
 
+impl_ignoring_stage_ref!(Copy);
+
 /// Parser directives are optional, and affect the way in which subsequent lines in a `Dockerfile`
 /// are handled.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#parser-directives).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Directive(Cow<'static, str>);
 
 impl Directive {
@@ -89,13 +157,14 @@ impl Directive {
 
 impl fmt::Display for Directive {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "# {}\n", &self.0)
+        writeln!(f, "# {}", &self.0)
     }
 }
 
 /// An `ENTRYPOINT` allows you to configure a container that will run as an executable.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#entrypoint).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Entrypoint(Cow<'static, str>);
 
 impl Entrypoint {
@@ -106,13 +175,14 @@ impl Entrypoint {
 
 impl fmt::Display for Entrypoint {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ENTRYPOINT {}\n", &self.0)
+        writeln!(f, "ENTRYPOINT {}", &self.0)
     }
 }
 
 /// The `ENV` instruction sets the environment variable `<key>` to the value `<value>`.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#env).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Env(Cow<'static, str>);
 
 impl Env {
@@ -123,7 +193,7 @@ impl Env {
 
 impl fmt::Display for Env {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ENV {}\n", &self.0)
+        writeln!(f, "ENV {}", &self.0)
     }
 }
 
@@ -131,6 +201,7 @@ impl fmt::Display for Env {
 /// ports at runtime.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#expose).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Expose(Cow<'static, str>);
 
 impl Expose {
@@ -141,7 +212,7 @@ impl Expose {
 
 impl fmt::Display for Expose {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "EXPOSE {}\n", &self.0)
+        writeln!(f, "EXPOSE {}", &self.0)
     }
 }
 
@@ -149,24 +220,51 @@ impl fmt::Display for Expose {
 /// instructions.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#from).
-pub struct From(Cow<'static, str>);
+///
+/// A `FROM` made with `From::stage` remembers the stage it was made from, as `Copy::from_stage`
+/// does.
+#[derive(Clone)]
+pub struct From(Cow<'static, str>, Option<StageRef>);
 
 impl From {
     pub fn new<T: Into<Cow<'static, str>>>(val: T) -> Self {
-        From(val.into())
+        From(val.into(), None)
+    }
+
+    /// Create a `FROM` instruction which builds upon the given stage.
+    pub fn stage(stage: &StageRef) -> Self {
+        From(stage.name().to_string().into(), Some(stage.clone()))
+    }
+
+    /// The stage this instruction was made from with `From::stage`, if any.
+    pub(crate) fn stage_ref(&self) -> Option<&StageRef> {
+        self.1.as_ref()
+    }
+
+    /// The image this instruction builds upon, which may be the name of an earlier stage.
+    pub fn image(&self) -> ImageRef<'_> {
+        ImageRef::parse(stage::split_from(&self.0).0)
+    }
+
+    /// The name this instruction gives its stage with `AS <name>`, if any.
+    pub fn stage_name(&self) -> Option<&str> {
+        stage::split_from(&self.0).1
     }
 }
 
 impl fmt::Display for From {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "FROM {}\n", &self.0)
+        writeln!(f, "FROM {}", &self.0)
     }
 }
 
+impl_ignoring_stage_ref!(From);
+
 /// The `HEALTHCHECK` instruction tells Docker how to test a container to check that it is still
 /// working.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#healthcheck).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Healthcheck(Cow<'static, str>);
 
 impl Healthcheck {
@@ -177,13 +275,14 @@ impl Healthcheck {
 
 impl fmt::Display for Healthcheck {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "HEALTHCHECK {}\n", &self.0)
+        writeln!(f, "HEALTHCHECK {}", &self.0)
     }
 }
 
 /// The `LABEL` instruction adds metadata to an image.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#label).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Label(Cow<'static, str>);
 
 impl Label {
@@ -194,7 +293,7 @@ impl Label {
 
 impl fmt::Display for Label {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "LABEL {}\n", &self.0)
+        writeln!(f, "LABEL {}", &self.0)
     }
 }
 
@@ -204,6 +303,7 @@ impl fmt::Display for Label {
 /// instruction in the downstream `Dockerfile`.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#onbuild).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Onbuild(Cow<'static, str>);
 
 impl Onbuild {
@@ -214,7 +314,7 @@ impl Onbuild {
 
 impl fmt::Display for Onbuild {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ONBUILD {}\n", &self.0)
+        writeln!(f, "ONBUILD {}", &self.0)
     }
 }
 
@@ -222,6 +322,7 @@ impl fmt::Display for Onbuild {
 /// commit the results.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#run).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Run(Cow<'static, str>);
 
 impl Run {
@@ -232,7 +333,7 @@ impl Run {
 
 impl fmt::Display for Run {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "RUN {}\n", &self.0)
+        writeln!(f, "RUN {}", &self.0)
     }
 }
 
@@ -240,6 +341,7 @@ impl fmt::Display for Run {
 /// overridden.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#shell).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Shell(Cow<'static, str>);
 
 impl Shell {
@@ -250,7 +352,7 @@ impl Shell {
 
 impl fmt::Display for Shell {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "SHELL {}\n", &self.0)
+        writeln!(f, "SHELL {}", &self.0)
     }
 }
 
@@ -258,6 +360,7 @@ impl fmt::Display for Shell {
 /// to exit.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#stopsignal).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Stopsignal(Cow<'static, str>);
 
 impl Stopsignal {
@@ -268,7 +371,7 @@ impl Stopsignal {
 
 impl fmt::Display for Stopsignal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "STOPSIGNAL {}\n", &self.0)
+        writeln!(f, "STOPSIGNAL {}", &self.0)
     }
 }
 
@@ -277,6 +380,7 @@ impl fmt::Display for Stopsignal {
 /// it in the `Dockerfile`.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#user).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct User(Cow<'static, str>);
 
 impl User {
@@ -287,7 +391,7 @@ impl User {
 
 impl fmt::Display for User {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "USER {}\n", &self.0)
+        writeln!(f, "USER {}", &self.0)
     }
 }
 
@@ -295,6 +399,7 @@ impl fmt::Display for User {
 /// externally mounted volumes from native host or other containers.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#volume).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Volume(Cow<'static, str>);
 
 impl Volume {
@@ -305,7 +410,7 @@ impl Volume {
 
 impl fmt::Display for Volume {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "VOLUME {}\n", &self.0)
+        writeln!(f, "VOLUME {}", &self.0)
     }
 }
 
@@ -313,6 +418,7 @@ impl fmt::Display for Volume {
 /// `COPY` and `ADD` instructions that follow it in the `Dockerfile`.
 ///
 /// [See the docs here](https://docs.docker.com/engine/reference/builder/#workdir).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Workdir(Cow<'static, str>);
 
 impl Workdir {
@@ -323,7 +429,7 @@ impl Workdir {
 
 impl fmt::Display for Workdir {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "WORKDIR {}\n", &self.0)
+        writeln!(f, "WORKDIR {}", &self.0)
     }
 }
 
@@ -336,6 +442,7 @@ impl fmt::Display for Workdir {
 /// [Dockerfile spec here](https://docs.docker.com/engine/reference/builder/). If you notice any
 /// missing instructions, please
 /// [open an issue here](https://github.com/thedodd/dockerfile/issues/new).
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Instruction {
     Add(Add),
     Arg(Arg),
@@ -382,6 +489,155 @@ impl fmt::Display for Instruction {
     }
 }
 
+impl Instruction {
+    /// The keyword which introduces this instruction, such as `RUN`.
+    ///
+    /// Parser directives have no keyword and are reported as `#`.
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Instruction::Add(_) => "ADD",
+            Instruction::Arg(_) => "ARG",
+            Instruction::Cmd(_) => "CMD",
+            Instruction::Copy(_) => "COPY",
+            Instruction::Directive(_) => "#",
+            Instruction::Entrypoint(_) => "ENTRYPOINT",
+            Instruction::Env(_) => "ENV",
+            Instruction::Expose(_) => "EXPOSE",
+            Instruction::From(_) => "FROM",
+            Instruction::Healthcheck(_) => "HEALTHCHECK",
+            Instruction::Label(_) => "LABEL",
+            Instruction::Onbuild(_) => "ONBUILD",
+            Instruction::Run(_) => "RUN",
+            Instruction::Shell(_) => "SHELL",
+            Instruction::Stopsignal(_) => "STOPSIGNAL",
+            Instruction::User(_) => "USER",
+            Instruction::Volume(_) => "VOLUME",
+            Instruction::Workdir(_) => "WORKDIR",
+        }
+    }
+}
+
+impl Instruction {
+    /// The raw arguments of this instruction, which is everything following the keyword.
+    pub fn value(&self) -> &str {
+        match self {
+            Instruction::Add(inst) => &inst.0,
+            Instruction::Arg(inst) => &inst.0,
+            Instruction::Cmd(inst) => &inst.0,
+            Instruction::Copy(inst) => &inst.0,
+            Instruction::Directive(inst) => &inst.0,
+            Instruction::Entrypoint(inst) => &inst.0,
+            Instruction::Env(inst) => &inst.0,
+            Instruction::Expose(inst) => &inst.0,
+            Instruction::From(inst) => &inst.0,
+            Instruction::Healthcheck(inst) => &inst.0,
+            Instruction::Label(inst) => &inst.0,
+            Instruction::Onbuild(inst) => &inst.0,
+            Instruction::Run(inst) => &inst.0,
+            Instruction::Shell(inst) => &inst.0,
+            Instruction::Stopsignal(inst) => &inst.0,
+            Instruction::User(inst) => &inst.0,
+            Instruction::Volume(inst) => &inst.0,
+            Instruction::Workdir(inst) => &inst.0,
+        }
+    }
+
+    /// The flags which this instruction accepts, according to the Dockerfile spec.
+    pub fn accepted_flags(&self) -> &'static [FlagSpec] {
+        match self {
+            Instruction::Add(_) => flags::ADD_FLAGS,
+            Instruction::Copy(_) => flags::COPY_FLAGS,
+            Instruction::From(_) => flags::FROM_FLAGS,
+            Instruction::Healthcheck(_) => flags::HEALTHCHECK_FLAGS,
+            Instruction::Run(_) => flags::RUN_FLAGS,
+            _ => &[],
+        }
+    }
+
+    /// Determine the form of this instruction's arguments, following any leading flags.
+    ///
+    /// Returns `None` for instructions which never accept a JSON array, and for a JSON array
+    /// containing anything other than strings, which Docker rejects.
+    pub fn form(&self) -> Option<Form<'_>> {
+        match self {
+            Instruction::Add(_) | Instruction::Cmd(_) | Instruction::Copy(_) | Instruction::Entrypoint(_)
+                | Instruction::Run(_) | Instruction::Shell(_) | Instruction::Volume(_) => (),
+            _ => return None,
+        }
+        let value = self.value();
+        let args = Flags::parse(value).map(|(_, rest)| rest).unwrap_or(value);
+        Form::detect(args)
+    }
+
+    /// Parse the leading flags of this instruction and check them against the flags it accepts.
+    ///
+    /// Parser directives never have flags.
+    pub fn flags(&self) -> Result<Flags, FlagError> {
+        if let Instruction::Directive(_) = self {
+            return Ok(Flags::default());
+        }
+        let (flags, _) = Flags::parse(self.value())?;
+        flags.check(self.accepted_flags())?;
+        Ok(flags)
+    }
+
+    /// This instruction, forgetting any `StageRef` it was made from, for when its stages are
+    /// renamed or renumbered.
+    pub(crate) fn without_stage_ref(&self) -> Instruction {
+        match self {
+            Instruction::Copy(Copy(value, Some(_))) => Instruction::Copy(Copy(value.clone(), None)),
+            Instruction::From(From(value, Some(_))) => Instruction::From(From(value.clone(), None)),
+            _ => self.clone(),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parser::parse_single(s, ExpectedInstruction::Any)
+    }
+}
+
+/// Implement `FromStr` for each of the given instruction types, parsing input which holds
+/// exactly one instruction of the expected kind.
+macro_rules! impl_from_str {
+    ($($name:ident => $expected:expr),* $(,)?) => {$(
+        impl FromStr for $name {
+            type Err = ParseError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match parser::parse_single(s, $expected)? {
+                    Instruction::$name(inst) => Ok(inst),
+                    _ => unreachable!("parse_single only returns instructions of the expected kind"),
+                }
+            }
+        }
+    )*};
+}
+
+impl_from_str! {
+    Add => ExpectedInstruction::Keyword("ADD"),
+    Arg => ExpectedInstruction::Keyword("ARG"),
+    Cmd => ExpectedInstruction::Keyword("CMD"),
+    Copy => ExpectedInstruction::Keyword("COPY"),
+    Directive => ExpectedInstruction::Directive,
+    Entrypoint => ExpectedInstruction::Keyword("ENTRYPOINT"),
+    Env => ExpectedInstruction::Keyword("ENV"),
+    Expose => ExpectedInstruction::Keyword("EXPOSE"),
+    From => ExpectedInstruction::Keyword("FROM"),
+    Healthcheck => ExpectedInstruction::Keyword("HEALTHCHECK"),
+    Label => ExpectedInstruction::Keyword("LABEL"),
+    Onbuild => ExpectedInstruction::Keyword("ONBUILD"),
+    Run => ExpectedInstruction::Keyword("RUN"),
+    Shell => ExpectedInstruction::Keyword("SHELL"),
+    Stopsignal => ExpectedInstruction::Keyword("STOPSIGNAL"),
+    User => ExpectedInstruction::Keyword("USER"),
+    Volume => ExpectedInstruction::Keyword("VOLUME"),
+    Workdir => ExpectedInstruction::Keyword("WORKDIR"),
+}
+
 impl convert::From<Add> for Instruction {
     fn from(inst: Add) -> Self {
 
@@ -0,0 +1,268 @@
+use std::{
+    char,
+    iter::Peekable,
+    str::Chars,
+};
+
+/// The reasons a string could not be decoded as a JSON array of strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum JsonError {
+    /// The input is not valid JSON, or is valid JSON which is not an array.
+    Invalid,
+    /// The input is a valid JSON array, but not every element is a string.
+    NotStrings,
+}
+
+/// Decode the input as a JSON array of strings, the way Docker's `parseJSON` does.
+///
+/// Docker decodes a single value with `json.NewDecoder(...).Decode`, which stops after the first
+/// complete value and ignores whatever follows it, so `["a"] b` is the array `["a"]`.
+pub(crate) fn parse_string_array(input: &str) -> Result<Vec<String>, JsonError> {
+    let mut reader = Reader{chars: input.chars().peekable()};
+    reader.skip_whitespace();
+    if reader.chars.peek() != Some(&'[') {
+        return Err(JsonError::Invalid);
+    }
+    match reader.value()? {
+        Value::Array(elems) => elems.into_iter()
+            .map(|elem| match elem {
+                Value::String(s) => Ok(s),
+                _ => Err(JsonError::NotStrings),
+            })
+            .collect(),
+        _ => Err(JsonError::Invalid),
+    }
+}
+
+/// Encode the given string as a JSON string literal.
+pub(crate) fn quote(input: &str) -> String {
+    let mut out = String::with_capacity(input.len() + 2);
+    out.push('"');
+    for c in input.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A decoded JSON value. Only strings and arrays retain their contents.
+///
+/// This is just capable enough to reproduce how Docker decodes JSON arrays.
+enum Value {
+    String(String),
+    Array(Vec<Value>),
+    Other,
+}
+
+struct Reader<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Reader<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.chars.peek() {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(JsonError::Invalid),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some('"') => self.string().map(Value::String),
+            Some('t') => self.literal("true"),
+            Some('f') => self.literal("false"),
+            Some('n') => self.literal("null"),
+            Some('-') | Some('0'..='9') => self.number(),
+            _ => Err(JsonError::Invalid),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, JsonError> {
+        self.expect('[')?;
+        let mut elems = vec![];
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(Value::Array(elems));
+        }
+        loop {
+            elems.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(elems)),
+                _ => return Err(JsonError::Invalid),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, JsonError> {
+        self.expect('{')?;
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Value::Other);
+        }
+        loop {
+            self.skip_whitespace();
+            self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.value()?;
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Other),
+                _ => return Err(JsonError::Invalid),
+            }
+        }
+    }
+
+    fn literal(&mut self, literal: &str) -> Result<Value, JsonError> {
+        for expected in literal.chars() {
+            self.expect(expected)?;
+        }
+        Ok(Value::Other)
+    }
+
+    fn number(&mut self) -> Result<Value, JsonError> {
+        if self.chars.peek() == Some(&'-') {
+            self.chars.next();
+        }
+        match self.chars.next() {
+            Some('0') => (),
+            Some('1'..='9') => self.digits(),
+            _ => return Err(JsonError::Invalid),
+        }
+        if self.chars.peek() == Some(&'.') {
+            self.chars.next();
+            self.digit()?;
+            self.digits();
+        }
+        if let Some('e') | Some('E') = self.chars.peek() {
+            self.chars.next();
+            if let Some('+') | Some('-') = self.chars.peek() {
+                self.chars.next();
+            }
+            self.digit()?;
+            self.digits();
+        }
+        Ok(Value::Other)
+    }
+
+    fn digit(&mut self) -> Result<(), JsonError> {
+        match self.chars.next() {
+            Some('0'..='9') => Ok(()),
+            _ => Err(JsonError::Invalid),
+        }
+    }
+
+    fn digits(&mut self) {
+        while let Some('0'..='9') = self.chars.peek() {
+            self.chars.next();
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut out = String::new();
+        loop {
+            match self.chars.next().ok_or(JsonError::Invalid)? {
+                '"' => return Ok(out),
+                '\\' => match self.chars.next().ok_or(JsonError::Invalid)? {
+                    '"' => out.push('"'),
+                    '\\' => out.push('\\'),
+                    '/' => out.push('/'),
+                    'b' => out.push('\u{8}'),
+                    'f' => out.push('\u{c}'),
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    'u' => out.push(self.unicode_escape()?),
+                    _ => return Err(JsonError::Invalid),
+                },
+                c if (c as u32) < 0x20 => return Err(JsonError::Invalid),
+                c => out.push(c),
+            }
+        }
+    }
+
+    /// Decode the body of a `\u` escape, combining surrogate pairs as Go does.
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let first = self.hex4()?;
+        if (0xD800..0xDC00).contains(&first) {
+            // The next escape is only consumed if it completes the pair. Otherwise it is decoded
+            // on its own, after the replacement character for the lone high surrogate.
+            let rest = self.chars.clone();
+            if self.chars.next() == Some('\\') && self.chars.next() == Some('u') {
+                if let Ok(second @ 0xDC00..=0xDFFF) = self.hex4() {
+                    let combined = 0x10000 + ((first - 0xD800) << 10) + (second - 0xDC00);
+                    return Ok(char::from_u32(combined).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+            }
+            self.chars = rest;
+            return Ok(char::REPLACEMENT_CHARACTER);
+        }
+        Ok(char::from_u32(first).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit = self.chars.next().and_then(|c| c.to_digit(16)).ok_or(JsonError::Invalid)?;
+            value = value * 16 + digit;
+        }
+        Ok(value)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_string_array_matches_go() {
+        assert_eq!(parse_string_array(r#" [ "a", "bé\"" ] "#), Ok(vec!["a".into(), "b\u{e9}\"".into()]));
+        assert_eq!(parse_string_array("[]"), Ok(vec![]));
+        assert_eq!(parse_string_array(r#"["a", 'b']"#), Err(JsonError::Invalid));
+        assert_eq!(parse_string_array(r#"["a",]"#), Err(JsonError::Invalid));
+        assert_eq!(parse_string_array(r#"["a"] b"#), Ok(vec!["a".into()]));
+        assert_eq!(parse_string_array(r#"["a"]]"#), Ok(vec!["a".into()]));
+        assert_eq!(parse_string_array(r#"["a", 1e5, {"b": [null]}]"#), Err(JsonError::NotStrings));
+    }
+
+    #[test]
+    fn parse_surrogates_as_go_does() {
+        assert_eq!(parse_string_array(r#"["\ud83d\ude00"]"#), Ok(vec!["\u{1f600}".into()]));
+        assert_eq!(parse_string_array(r#"["\ud800\u0041"]"#), Ok(vec!["\u{fffd}A".into()]));
+        assert_eq!(parse_string_array(r#"["\ud800\ud800\udc00"]"#), Ok(vec!["\u{fffd}\u{10000}".into()]));
+        assert_eq!(parse_string_array(r#"["\ud800\n", "\udc00"]"#), Ok(vec!["\u{fffd}\n".into(), "\u{fffd}".into()]));
+        assert_eq!(parse_string_array(r#"["\ud800\uzzzz"]"#), Err(JsonError::Invalid));
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        let input = "say \"hi\"\\\n\u{1}";
+        assert_eq!(parse_string_array(&format!("[{}]", quote(input))), Ok(vec![input.to_string()]));
+    }
+}
@@ -1,14 +1,56 @@
 #![cfg_attr(feature="docinclude", feature(external_doc))]
 #![cfg_attr(feature="docinclude", doc(include="../README.md"))]
 
+pub mod fmt;
+pub mod lint;
+pub mod shell;
+
+mod args;
 mod builder;
+mod diagnostic;
+mod digest;
+mod flags;
+mod form;
+mod graph;
+mod image;
 mod instructions;
+mod json;
+mod merge;
+mod parser;
+mod render;
+mod stage;
+mod validate;
 
 pub use crate::{
+    args::{
+        ArgDecl,
+        ArgInScope,
+        ArgScope,
+    },
     builder::{
         Dockerfile,
         DockerfileBuilder,
+        Provenance,
+    },
+    diagnostic::{
+        Diagnostic,
+        Severity,
+        Span,
+    },
+    digest::Digest,
+    flags::{
+        Flag,
+        FlagError,
+        FlagKind,
+        FlagSpec,
+        Flags,
+    },
+    form::Form,
+    graph::{
+        StageEdge,
+        StageGraph,
     },
+    image::ImageRef,
     instructions::{
         Add,
         Arg,
@@ -30,6 +72,31 @@ pub use crate::{
         Workdir,
         Instruction,
     },
+    merge::{
+        MergeError,
+        MergeOptions,
+    },
+    parser::{
+        ExpectedInstruction,
+        InstructionReader,
+        ParseError,
+        ParseErrorKind,
+    },
+    render::{
+        KeywordCase,
+        LineEnding,
+        RenderOptions,
+        SourceMap,
+        SourceMapping,
+    },
+    stage::{
+        EdgeKind,
+        RenameError,
+        Stage,
+        StageBuilder,
+        StageRef,
+        StageRefError,
+    },
 };
 
 //////////////////////////////////////////////////////////////////////////////////////////////////
@@ -0,0 +1,425 @@
+use crate::{
+    builder::Dockerfile,
+    lint::{
+        parse_run_scripts,
+        run_scripts,
+        Finding,
+        LintRule,
+    },
+    shell::{
+        effective_shells,
+        Command,
+    },
+    stage::range_within,
+};
+
+/// The script of a `RUN` should be valid shell syntax.
+///
+/// Other rules which look inside `RUN` scripts skip those which cannot be parsed, so this reports
+/// them rather than letting them go unchecked. Scripts run by a `SHELL` other than a POSIX shell
+/// are not checked.
+pub struct ShellSyntax;
+
+impl LintRule for ShellSyntax {
+    fn id(&self) -> &'static str {
+        "ShellSyntax"
+    }
+
+    fn description(&self) -> &'static str {
+        "RUN scripts should be valid shell syntax"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, text, script) in parse_run_scripts(dockerfile) {
+            if let Err(error) = script {
+                let range = range_within(dockerfile.instructions()[idx].value(), text);
+                let message = format!("The script could not be parsed: {}", error.kind());
+                findings.push(Finding::new(idx, message).with_range(range.start + error.offset()..range.end));
+            }
+        }
+        findings
+    }
+}
+
+/// `cd` should not be used to change directory for later commands, which `WORKDIR` does more
+/// clearly and for every following instruction.
+///
+/// A `cd` within a subshell or other compound command is not flagged, as it is usually meant to
+/// apply to only part of the script.
+pub struct UseWorkdir;
+
+impl LintRule for UseWorkdir {
+    fn id(&self) -> &'static str {
+        "UseWorkdir"
+    }
+
+    fn description(&self) -> &'static str {
+        "WORKDIR should be used to change directory"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, script) in run_scripts(dockerfile) {
+            let value = dockerfile.instructions()[idx].value();
+            let top_level = script.lists.iter().flat_map(|list| list.pipelines()).flat_map(|pipeline| &pipeline.commands);
+            for command in top_level.filter(|command| command.name().as_deref() == Some("cd")) {
+                let suggestion = match command.args().first().and_then(|dir| dir.literal()) {
+                    Some(dir) => format!("Replace '{}' with 'WORKDIR {}'", command.text, dir),
+                    None => "Replace the cd with a WORKDIR instruction".to_string(),
+                };
+                let finding = Finding::new(idx, "Use WORKDIR to switch to a directory").with_suggestion(suggestion);
+                findings.push(finding.with_range(range_within(value, command.text)));
+            }
+        }
+        findings
+    }
+}
+
+/// `sudo` should not be used within an image, as builds already run as root unless `USER` says
+/// otherwise, and `sudo` has unpredictable TTY and signal handling.
+pub struct NoSudo;
+
+impl LintRule for NoSudo {
+    fn id(&self) -> &'static str {
+        "NoSudo"
+    }
+
+    fn description(&self) -> &'static str {
+        "sudo should not be used"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, script) in run_scripts(dockerfile) {
+            let value = dockerfile.instructions()[idx].value();
+            for command in script.commands().into_iter().filter(|command| command.name().as_deref() == Some("sudo")) {
+                let finding = Finding::new(idx, "Do not use sudo, as it leads to unpredictable behavior")
+                    .with_suggestion("Remove sudo, and use USER to run the commands which must not run as root");
+                findings.push(finding.with_range(range_within(value, command.words()[0].text)));
+            }
+        }
+        findings
+    }
+}
+
+/// Pipelines should be run with `set -o pipefail`, as otherwise the exit status of a pipeline is
+/// that of its last command, and the failure of any other command goes unnoticed.
+///
+/// Pipefail is set by `set -o pipefail` earlier in the same `RUN`, or by a `SHELL` which passes
+/// `-o pipefail`, including one inherited from the stage this stage is built `FROM`. Either may
+/// turn it back off with `+o pipefail`.
+pub struct MissingPipefail;
+
+impl LintRule for MissingPipefail {
+    fn id(&self) -> &'static str {
+        "MissingPipefail"
+    }
+
+    fn description(&self) -> &'static str {
+        "Pipelines should be run with pipefail set"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let shells = effective_shells(dockerfile);
+        let mut findings = vec![];
+        for (idx, script) in run_scripts(dockerfile) {
+            let value = dockerfile.instructions()[idx].value();
+            let shell = shells[idx].as_ref().and_then(|shell| pipefail_option(shell.iter().skip(1).map(String::as_str)));
+            let sets: Vec<_> = script.commands().into_iter().filter_map(|command| {
+                let setting = set_pipefail(command)?;
+                Some((range_within(value, command.text).start, setting))
+            }).collect();
+            let pipefail_at = |start: usize| {
+                sets.iter().rev().find(|(at, _)| *at < start).map(|(_, setting)| *setting).or(shell).unwrap_or(false)
+            };
+            let unsafe_pipe = script.pipelines().into_iter()
+                .filter(|pipeline| pipeline.commands.len() > 1)
+                .find(|pipeline| !pipefail_at(range_within(value, pipeline.text).start));
+            if let Some(pipeline) = unsafe_pipe {
+                let message = format!("Pipeline '{}' fails only if its last command does", pipeline.text);
+                let finding = Finding::new(idx, message)
+                    .with_suggestion("Begin the command with 'set -o pipefail &&', or use SHELL [\"/bin/bash\", \"-o\", \"pipefail\", \"-c\"]");
+                findings.push(finding.with_range(range_within(value, pipeline.text)));
+            }
+        }
+        findings
+    }
+}
+
+/// Scripts should not be downloaded and piped straight into a shell, which runs whatever the
+/// server returns without any chance to verify it.
+pub struct CurlPipeShell;
+
+impl LintRule for CurlPipeShell {
+    fn id(&self) -> &'static str {
+        "CurlPipeShell"
+    }
+
+    fn description(&self) -> &'static str {
+        "Downloads should not be piped into a shell"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, script) in run_scripts(dockerfile) {
+            let value = dockerfile.instructions()[idx].value();
+            for pipeline in script.pipelines() {
+                // A shell run with sudo is still a shell.
+                let names: Vec<_> = pipeline.commands.iter()
+                    .map(|command| match command.name().as_deref() {
+                        Some("sudo") => command.args().first().and_then(|arg| arg.literal()),
+                        _ => command.name(),
+                    })
+                    .map(Option::unwrap_or_default)
+                    .collect();
+                let download = match names.iter().position(|name| name == "curl" || name == "wget") {
+                    Some(download) => download,
+                    None => continue,
+                };
+                let shell = names[download + 1..].iter().find(|name| {
+                    matches!(name.rsplit('/').next(), Some("sh") | Some("bash") | Some("dash") | Some("ash") | Some("zsh"))
+                });
+                if let Some(shell) = shell {
+                    let message = format!("Piping a download from {} into {} runs it without verifying it", names[download], shell);
+                    let finding = Finding::new(idx, message)
+                        .with_suggestion("Download the script to a file, verify its checksum, then run it");
+                    findings.push(finding.with_range(range_within(value, pipeline.text)));
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// Downloads should fail the build when the server responds with an HTTP error, rather than
+/// saving the error page in place of the file.
+///
+/// `curl` succeeds on HTTP errors unless given `--fail`. `wget` fails on them by default, so is
+/// flagged only when given `--content-on-error`.
+pub struct DownloadWithoutFail;
+
+impl LintRule for DownloadWithoutFail {
+    fn id(&self) -> &'static str {
+        "DownloadWithoutFail"
+    }
+
+    fn description(&self) -> &'static str {
+        "Downloads should fail on HTTP errors"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, script) in run_scripts(dockerfile) {
+            let value = dockerfile.instructions()[idx].value();
+            for command in script.commands() {
+                let args: Vec<_> = command.args().iter().map(|arg| arg.unquoted()).collect();
+                let (message, suggestion) = match command.name().as_deref() {
+                    Some("curl") if !args.iter().any(|arg| curl_fails(arg)) => (
+                        "curl succeeds even when the server responds with an HTTP error",
+                        "Use 'curl --fail', or '-f', so that HTTP errors fail the build",
+                    ),
+                    Some("wget") if args.iter().any(|arg| arg == "--content-on-error") => (
+                        "wget --content-on-error saves the response to an HTTP error",
+                        "Remove '--content-on-error', so that HTTP errors fail the build",
+                    ),
+                    _ => continue,
+                };
+                let finding = Finding::new(idx, message).with_suggestion(suggestion);
+                findings.push(finding.with_range(range_within(value, command.text)));
+            }
+        }
+        findings
+    }
+}
+
+/// `chmod 777` should not be used, as it makes files writable and executable by every user.
+/// When the files come from an earlier layer, changing their mode also copies every one of them
+/// into a new layer.
+pub struct ChmodWorldWritable;
+
+impl LintRule for ChmodWorldWritable {
+    fn id(&self) -> &'static str {
+        "ChmodWorldWritable"
+    }
+
+    fn description(&self) -> &'static str {
+        "Files should not be made writable by every user"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, script) in run_scripts(dockerfile) {
+            let value = dockerfile.instructions()[idx].value();
+            for command in script.commands().into_iter().filter(|command| command.name().as_deref() == Some("chmod")) {
+                let mode = command.args().iter().find(|arg| {
+                    arg.literal().is_some_and(|mode| matches!(mode.as_str(), "777" | "0777" | "a+rwx" | "ugo+rwx" | "a=rwx" | "ugo=rwx"))
+                });
+                if let Some(mode) = mode {
+                    let message = format!("chmod {} makes files writable by every user", mode.text);
+                    let finding = Finding::new(idx, message).with_suggestion(
+                        "Grant only the permissions needed, such as 755, and set modes when adding files with 'COPY --chmod'",
+                    );
+                    findings.push(finding.with_range(range_within(value, mode.text)));
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// Whether the given command is `set`, turning pipefail on or off.
+fn set_pipefail(command: &Command<'_>) -> Option<bool> {
+    if command.name().as_deref() != Some("set") {
+        return None;
+    }
+    let args: Vec<_> = command.args().iter().map(|arg| arg.literal()).collect();
+    pipefail_option(args.iter().map_while(Option::as_deref))
+}
+
+/// Whether the given options of `set`, or of a shell, turn pipefail on with `-o pipefail` or off
+/// with `+o pipefail`, if they mention it at all. The last mention wins.
+///
+/// Options may be grouped, as in `-euo pipefail`. The options end at the first argument which
+/// is not one, such as the `-c` given to a shell.
+fn pipefail_option<'a, I: IntoIterator<Item = &'a str>>(args: I) -> Option<bool> {
+    let mut setting = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (on, options) = match (arg.strip_prefix('-'), arg.strip_prefix('+')) {
+            (Some(options), _) => (true, options),
+            (_, Some(options)) => (false, options),
+            _ => break,
+        };
+        if options.is_empty() || !options.chars().all(|c| c.is_ascii_alphabetic()) || options.contains('c') {
+            break;
+        }
+        if options.ends_with('o') && args.next() == Some("pipefail") {
+            setting = Some(on);
+        }
+    }
+    setting
+}
+
+/// Whether the given argument of `curl` makes it fail on HTTP errors.
+fn curl_fails(arg: &str) -> bool {
+    match arg.strip_prefix('-') {
+        Some(long) if long.starts_with('-') => matches!(long, "-fail" | "-fail-with-body"),
+        Some(short) => short.contains('f'),
+        None => false,
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lint::{
+        check,
+        messages,
+    };
+
+    fn suggestions<R: LintRule>(rule: R, input: &str) -> Vec<(String, Option<String>)> {
+        check(rule, input).into_iter().map(|finding| (finding.message, finding.suggestion)).collect()
+    }
+
+    #[test]
+    fn shell_syntax() {
+        let input = "RUN --mount=type=cache,target=/root/.cache echo 'a\nRUN echo $((cd /tmp; ls) )\nSHELL [\"pwsh\", \"-c\"]\nRUN echo 'a\n";
+        let findings = check(ShellSyntax, input);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].message, "The script could not be parsed: unterminated single quote");
+        assert_eq!(findings[0].range, Some(44..46));
+    }
+
+    #[test]
+    fn use_workdir() {
+        let input = "RUN cd /app && make\nRUN (cd /tmp && make) && cd \"$HOME\"\n";
+        assert_eq!(suggestions(UseWorkdir, input), vec![
+            ("Use WORKDIR to switch to a directory".to_string(), Some("Replace 'cd /app' with 'WORKDIR /app'".to_string())),
+            ("Use WORKDIR to switch to a directory".to_string(), Some("Replace the cd with a WORKDIR instruction".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn no_sudo() {
+        let findings = check(NoSudo, "RUN make && sudo make install\nRUN echo sudo\n");
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].range, Some(8..12));
+    }
+
+    #[test]
+    fn missing_pipefail() {
+        let input = r##"RUN curl -f https://example.com/a | tar -x
+RUN set -euo pipefail && curl -f https://example.com/a | tar -x
+RUN echo a | cat; set -o pipefail
+RUN echo $(ls | wc -l)
+SHELL ["/bin/bash", "-o", "pipefail", "-c"]
+RUN curl -f https://example.com/a | tar -x
+RUN set +o pipefail; grep a b | wc -l
+RUN set -eux && test -f a | cat
+"##;
+        assert_eq!(messages(MissingPipefail, input), vec![
+            "Pipeline 'curl -f https://example.com/a | tar -x' fails only if its last command does",
+            "Pipeline 'echo a | cat' fails only if its last command does",
+            "Pipeline 'ls | wc -l' fails only if its last command does",
+            "Pipeline 'grep a b | wc -l' fails only if its last command does",
+        ]);
+    }
+
+    #[test]
+    fn missing_pipefail_follows_shell() {
+        let input = r##"FROM debian AS base
+SHELL ["/bin/bash", "-euxo", "pipefail", "-c"]
+FROM base
+RUN echo a | cat
+FROM base
+SHELL ["/bin/bash", "-o", "pipefail", "+o", "pipefail", "-c"]
+RUN echo b | cat
+FROM debian
+RUN echo c | cat
+"##;
+        assert_eq!(messages(MissingPipefail, input), vec![
+            "Pipeline 'echo b | cat' fails only if its last command does",
+            "Pipeline 'echo c | cat' fails only if its last command does",
+        ]);
+    }
+
+    #[test]
+    fn non_posix_shells_are_skipped() {
+        let input = "SHELL [\"powershell\", \"-Command\"]\nRUN Get-ChildItem C:\\ | Select-Object Name; cd C:\\app\n";
+        assert_eq!(messages(MissingPipefail, input), Vec::<String>::new());
+        assert_eq!(messages(UseWorkdir, input), Vec::<String>::new());
+        let input = "SHELL [\"C:\\\\Windows\\\\System32\\\\cmd.exe\", \"/S\", \"/C\"]\nRUN cd C:\\app\nSHELL [\"/bin/sh\", \"-c\"]\nRUN cd /app\n";
+        assert_eq!(messages(UseWorkdir, input).len(), 1);
+    }
+
+    #[test]
+    fn curl_pipe_shell() {
+        let input = "RUN curl -fsSL https://example.com/install.sh | sudo bash -s\nRUN wget -qO- https://example.com/a.tgz | tar -xz\n";
+        assert_eq!(suggestions(CurlPipeShell, input), vec![(
+            "Piping a download from curl into bash runs it without verifying it".to_string(),
+            Some("Download the script to a file, verify its checksum, then run it".to_string()),
+        )]);
+    }
+
+    #[test]
+    fn download_without_fail() {
+        let input = "RUN curl -sSL -o a https://example.com/a\nRUN curl -fsSL -o a https://example.com/a && curl --fail-with-body b\nRUN wget -q https://example.com/a && wget --content-on-error b\n";
+        assert_eq!(messages(DownloadWithoutFail, input), vec![
+            "curl succeeds even when the server responds with an HTTP error",
+            "wget --content-on-error saves the response to an HTTP error",
+        ]);
+    }
+
+    #[test]
+    fn chmod_world_writable() {
+        let input = "RUN chmod -R 777 /app\nRUN chmod 755 /app && chmod a+rwx /tmp/x\n";
+        assert_eq!(messages(ChmodWorldWritable, input), vec![
+            "chmod 777 makes files writable by every user",
+            "chmod a+rwx makes files writable by every user",
+        ]);
+    }
+}
@@ -0,0 +1,331 @@
+//! Pluggable lint rules for Dockerfiles.
+//!
+//! A `Linter` holds a registry of `LintRule`s, each of which inspects a `Dockerfile` and reports
+//! `Finding`s. The linter turns those findings into `Diagnostic`s carrying the ID of the rule,
+//! its severity and the span of the instruction concerned.
+//!
+//! `Linter::default()` runs the recommended rules, and `Linter::strict()` adds rules which are
+//! too demanding for every project, such as pinning base images to digests and packages to
+//! versions. Custom rules are added by implementing `LintRule` and registering them with
+//! `Linter::register`.
+
+mod hygiene;
+mod packages;
+mod pinning;
+mod secrets;
+mod style;
+
+use std::ops::Range;
+
+use crate::{
+    builder::Dockerfile,
+    diagnostic::{
+        Diagnostic,
+        Severity,
+        Span,
+    },
+    form::Form,
+    instructions::Instruction,
+    shell::{
+        self,
+        effective_shells,
+        is_posix_shell,
+        Script,
+        ShellError,
+    },
+};
+
+pub use self::hygiene::{
+    ChmodWorldWritable,
+    CurlPipeShell,
+    DownloadWithoutFail,
+    MissingPipefail,
+    NoSudo,
+    ShellSyntax,
+    UseWorkdir,
+};
+pub use self::packages::{
+    AptNoInstallRecommends,
+    AptUpgrade,
+    PackageCacheCleanup,
+    PackageManagerYes,
+    UnpinnedPackage,
+};
+pub use self::pinning::{
+    FloatingMajorTag,
+    LatestTag,
+    MissingDigest,
+    UntaggedImage,
+};
+pub use self::secrets::SecretLeak;
+pub use self::style::{
+    JsonArgsRecommended,
+    StageNameCasing,
+    WorkdirRelativePath,
+};
+
+/// A single lint rule.
+pub trait LintRule {
+    /// The unique ID of this rule, such as `StageNameCasing`.
+    fn id(&self) -> &'static str;
+
+    /// A short description of what this rule checks.
+    fn description(&self) -> &'static str;
+
+    /// The severity of this rule's findings, unless overridden with `Linter::set_severity`.
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    /// Check the given Dockerfile, returning everything this rule finds wrong with it.
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding>;
+}
+
+/// A problem found by a lint rule within a single instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    /// The index of the instruction within its Dockerfile.
+    pub instruction: usize,
+
+    /// The byte range of the problem within the instruction's arguments, or `None` for the
+    /// whole instruction.
+    pub range: Option<Range<usize>>,
+
+    /// A human readable description of the problem.
+    pub message: String,
+
+    /// A suggested fix for the problem, if any.
+    pub suggestion: Option<String>,
+}
+
+impl Finding {
+    /// Create a new finding about the whole of the instruction at the given index.
+    pub fn new<T: Into<String>>(instruction: usize, message: T) -> Self {
+        Finding{instruction, range: None, message: message.into(), suggestion: None}
+    }
+
+    /// Narrow this finding to the given byte range within the instruction's arguments.
+    pub fn with_range(mut self, range: Range<usize>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Suggest how to fix the problem.
+    pub fn with_suggestion<T: Into<String>>(mut self, suggestion: T) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+}
+
+/// A registry of lint rules, which runs them over Dockerfiles.
+pub struct Linter {
+    rules: Vec<(Box<dyn LintRule>, Option<Severity>)>,
+}
+
+impl Default for Linter {
+    /// A linter running the recommended rules.
+    fn default() -> Self {
+        Linter{rules: recommended().into_iter().map(|rule| (rule, None)).collect()}
+    }
+}
+
+impl Linter {
+    /// Create a new linter without any rules.
+    pub fn new() -> Self {
+        Linter{rules: vec![]}
+    }
+
+    /// Create a new linter running the strict rules.
+    pub fn strict() -> Self {
+        Linter{rules: strict().into_iter().map(|rule| (rule, None)).collect()}
+    }
+
+    /// Register a rule with this linter, replacing any rule with the same ID.
+    pub fn register<R: LintRule + 'static>(&mut self, rule: R) -> &mut Self {
+        self.disable(rule.id());
+        self.rules.push((Box::new(rule), None));
+        self
+    }
+
+    /// Remove the rule with the given ID from this linter.
+    pub fn disable(&mut self, id: &str) -> &mut Self {
+        self.rules.retain(|(rule, _)| rule.id() != id);
+        self
+    }
+
+    /// Report the findings of the rule with the given ID at the given severity.
+    pub fn set_severity(&mut self, id: &str, severity: Severity) -> &mut Self {
+        for (rule, overridden) in &mut self.rules {
+            if rule.id() == id {
+                *overridden = Some(severity);
+            }
+        }
+        self
+    }
+
+    /// The rules of this linter, in the order in which they were registered.
+    pub fn rules(&self) -> impl Iterator<Item=&dyn LintRule> {
+        self.rules.iter().map(|(rule, _)| rule.as_ref())
+    }
+
+    /// Run every rule over the given Dockerfile, returning the diagnostics ordered by line.
+    pub fn lint(&self, dockerfile: &Dockerfile) -> Vec<Diagnostic> {
+        let lines = dockerfile.report_lines();
+        let mut diagnostics = vec![];
+        for (rule, severity) in &self.rules {
+            for finding in rule.check(dockerfile) {
+                let value_len = dockerfile.instructions().get(finding.instruction).map_or(0, |inst| inst.value().len());
+                diagnostics.push(Diagnostic{
+                    severity: severity.unwrap_or_else(|| rule.severity()),
+                    line: lines[finding.instruction],
+                    message: finding.message,
+                    rule: Some(rule.id()),
+                    span: Some(Span{instruction: finding.instruction, range: finding.range.unwrap_or(0..value_len)}),
+                    suggestion: finding.suggestion,
+                });
+            }
+        }
+        diagnostics.sort_by_key(|diag| diag.line);
+        diagnostics
+    }
+}
+
+impl Dockerfile {
+    /// Run the recommended lint rules over this Dockerfile.
+    ///
+    /// Use a `Linter` to choose the rules.
+    pub fn lint(&self) -> Vec<Diagnostic> {
+        Linter::default().lint(self)
+    }
+}
+
+/// The recommended rules, which `Linter::default` runs.
+pub fn recommended() -> Vec<Box<dyn LintRule>> {
+    vec![
+        Box::new(JsonArgsRecommended),
+        Box::new(StageNameCasing),
+        Box::new(WorkdirRelativePath),
+        Box::new(UntaggedImage),
+        Box::new(LatestTag),
+        Box::new(FloatingMajorTag),
+        Box::new(PackageManagerYes),
+        Box::new(AptNoInstallRecommends),
+        Box::new(PackageCacheCleanup),
+        Box::new(AptUpgrade),
+        Box::new(ShellSyntax),
+        Box::new(UseWorkdir),
+        Box::new(NoSudo),
+        Box::new(MissingPipefail),
+        Box::new(CurlPipeShell),
+        Box::new(DownloadWithoutFail),
+        Box::new(ChmodWorldWritable),
+        Box::new(SecretLeak),
+    ]
+}
+
+/// The strict rules, which `Linter::strict` runs. These are the recommended rules, along with
+/// `MissingDigest` and `UnpinnedPackage`.
+pub fn strict() -> Vec<Box<dyn LintRule>> {
+    let mut rules = recommended();
+    rules.push(Box::new(MissingDigest));
+    rules.push(Box::new(UnpinnedPackage));
+    rules
+}
+
+/// Parse the script of every shell form `RUN` of the Dockerfile, along with the index of the
+/// `RUN`. Scripts which cannot be parsed, or which are run by a `SHELL` other than a POSIX shell,
+/// are skipped; `ShellSyntax` reports those which cannot be parsed.
+pub(crate) fn run_scripts(dockerfile: &Dockerfile) -> Vec<(usize, Script<'_>)> {
+    parse_run_scripts(dockerfile).into_iter().filter_map(|(index, _, script)| Some((index, script.ok()?))).collect()
+}
+
+/// The text of every shell form `RUN` of the Dockerfile run by a POSIX shell, along with the index
+/// of the `RUN` and the result of parsing the text.
+pub(crate) fn parse_run_scripts(dockerfile: &Dockerfile) -> Vec<(usize, &str, Result<Script<'_>, ShellError>)> {
+    let shells = effective_shells(dockerfile);
+    let mut scripts = vec![];
+    for (index, instruction) in dockerfile.instructions().iter().enumerate() {
+        if shells[index].as_deref().is_some_and(|shell| !is_posix_shell(shell)) {
+            continue;
+        }
+        if let (Instruction::Run(_), Some(Form::Shell(text))) = (instruction, instruction.form()) {
+            scripts.push((index, text, shell::parse(text)));
+        }
+    }
+    scripts
+}
+
+/// Run the given rule over the given instructions, which follow a `FROM debian:bookworm-slim`,
+/// for the tests of the rules.
+#[cfg(test)]
+pub(crate) fn check<R: LintRule>(rule: R, input: &str) -> Vec<Finding> {
+    rule.check(&Dockerfile::parse(&format!("FROM debian:bookworm-slim\n{}", input)).unwrap())
+}
+
+/// The messages of the findings of `check`.
+#[cfg(test)]
+pub(crate) fn messages<R: LintRule>(rule: R, input: &str) -> Vec<String> {
+    check(rule, input).into_iter().map(|finding| finding.message).collect()
+}
+
+/// The instruction index and message of each finding of `check`.
+#[cfg(test)]
+pub(crate) fn findings<R: LintRule>(rule: R, input: &str) -> Vec<(usize, String)> {
+    check(rule, input).into_iter().map(|finding| (finding.instruction, finding.message)).collect()
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A company rule, forbidding images from outside of an internal registry.
+    struct InternalRegistry;
+
+    impl LintRule for InternalRegistry {
+        fn id(&self) -> &'static str {
+            "InternalRegistry"
+        }
+
+        fn description(&self) -> &'static str {
+            "Base images must come from registry.example.com"
+        }
+
+        fn severity(&self) -> Severity {
+            Severity::Error
+        }
+
+        fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+            dockerfile.stages().iter()
+                .filter(|stage| !stage.base().starts_with("registry.example.com/"))
+                .map(|stage| Finding::new(stage.range().start, format!("{} is not from the internal registry", stage.base())))
+                .collect()
+        }
+    }
+
+    const INPUT: &str = "FROM alpine AS Build\nWORKDIR app\nCMD run\n";
+
+    #[test]
+    fn linter_runs_registered_rules() {
+        let dockerfile = Dockerfile::parse(INPUT).unwrap();
+        let ids: Vec<_> = dockerfile.lint().iter().map(|diag| diag.rule.unwrap()).collect();
+        assert_eq!(ids, vec!["StageNameCasing", "UntaggedImage", "WorkdirRelativePath", "JSONArgsRecommended"]);
+        let strict = Linter::strict().lint(&dockerfile);
+        assert_eq!(strict.iter().filter(|diag| diag.rule == Some("MissingDigest")).count(), 1);
+
+        let mut linter = Linter::new();
+        linter.register(InternalRegistry).register(StageNameCasing).set_severity("StageNameCasing", Severity::Error);
+        assert_eq!(linter.rules().map(|rule| rule.id()).collect::<Vec<_>>(), vec!["InternalRegistry", "StageNameCasing"]);
+        let diagnostics = linter.lint(&dockerfile);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].to_string(), "error[InternalRegistry]: line 1: alpine is not from the internal registry");
+        assert_eq!(diagnostics[0].span, Some(Span{instruction: 0, range: 0..15}));
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+        assert_eq!(diagnostics[1].span, Some(Span{instruction: 0, range: 10..15}));
+
+        linter.disable("InternalRegistry");
+        assert_eq!(linter.lint(&dockerfile).len(), 1);
+    }
+}
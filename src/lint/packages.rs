@@ -0,0 +1,434 @@
+use crate::{
+    builder::Dockerfile,
+    lint::{
+        run_scripts,
+        Finding,
+        LintRule,
+    },
+    shell::{
+        Command,
+        Word,
+    },
+    stage::range_within,
+};
+
+/// Installing packages should not prompt for confirmation, which fails a non-interactive build.
+pub struct PackageManagerYes;
+
+impl LintRule for PackageManagerYes {
+    fn id(&self) -> &'static str {
+        "PackageManagerYes"
+    }
+
+    fn description(&self) -> &'static str {
+        "apt-get, yum and dnf should be run with -y"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for run in package_runs(dockerfile) {
+            for command in &run.commands {
+                let prompts = match command.manager {
+                    Manager::Apt => matches!(command.subcommand.as_str(), "install" | "upgrade" | "dist-upgrade" | "full-upgrade" | "remove" | "purge"),
+                    Manager::Yum => matches!(command.subcommand.as_str(), "install" | "update" | "upgrade" | "remove" | "erase"),
+                    _ => false,
+                };
+                if prompts && !command.assumes_yes() {
+                    let message = format!("Use '{} {} -y' to avoid prompting for confirmation", command.program, command.subcommand);
+                    findings.push(Finding::new(run.index, message).with_range(range_within(run.value, command.text)));
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// `apt-get install` should be given `--no-install-recommends`, so that only the packages asked
+/// for and their dependencies end up in the image.
+pub struct AptNoInstallRecommends;
+
+impl LintRule for AptNoInstallRecommends {
+    fn id(&self) -> &'static str {
+        "AptNoInstallRecommends"
+    }
+
+    fn description(&self) -> &'static str {
+        "apt-get install should be run with --no-install-recommends"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for run in package_runs(dockerfile) {
+            for command in &run.commands {
+                if command.manager != Manager::Apt || command.subcommand != "install" {
+                    continue;
+                }
+                let avoided = command.args.iter().any(|(_, arg)| {
+                    arg == "--no-install-recommends" || arg.eq_ignore_ascii_case("APT::Install-Recommends=false")
+                });
+                if !avoided {
+                    let message = format!("Use '{} install --no-install-recommends' to avoid installing recommended packages", command.program);
+                    findings.push(Finding::new(run.index, message).with_range(range_within(run.value, command.text)));
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// Package manager caches should be removed in the same `RUN` which fills them, as removing them
+/// in a later layer leaves them in the image.
+///
+/// A `RUN` with a cache mount keeps its caches out of the image, so is never flagged.
+pub struct PackageCacheCleanup;
+
+impl LintRule for PackageCacheCleanup {
+    fn id(&self) -> &'static str {
+        "PackageCacheCleanup"
+    }
+
+    fn description(&self) -> &'static str {
+        "Package manager caches should be cleaned up in the same layer"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for run in package_runs(dockerfile) {
+            let cache_mount = dockerfile.instructions()[run.index].flags()
+                .is_ok_and(|flags| flags.get_all("mount").any(|mount| mount.split(',').any(|opt| opt == "type=cache")));
+            if cache_mount {
+                continue;
+            }
+            for command in &run.commands {
+                let suggestion = match (command.manager, command.subcommand.as_str()) {
+                    (Manager::Apt, "install") if !run.removes("/var/lib/apt/lists") => {
+                        "remove /var/lib/apt/lists/* after installing packages"
+                    }
+                    (Manager::Apk, "add") if !command.has_option("--no-cache") && !run.removes("/var/cache/apk") => {
+                        "use 'apk add --no-cache'"
+                    }
+                    (Manager::Yum, "install") if !run.cleans(&command.program, "all") && !run.removes("/var/cache/") => {
+                        "run 'clean all' after installing packages"
+                    }
+                    (Manager::Pip, "install") if !command.has_option("--no-cache-dir") => {
+                        "use 'pip install --no-cache-dir'"
+                    }
+                    (Manager::Npm, "install") | (Manager::Npm, "ci") if !run.cleans("npm", "clean") => {
+                        "run 'npm cache clean --force' after installing packages"
+                    }
+                    _ => continue,
+                };
+                let message = format!("'{} {}' leaves its cache in the layer, {}", command.program, command.subcommand, suggestion);
+                findings.push(Finding::new(run.index, message).with_range(range_within(run.value, command.text)));
+            }
+        }
+        findings
+    }
+}
+
+/// Packages should be pinned to a version, so that rebuilding the image installs the same ones.
+///
+/// Packages given by path or URL, or from variables, are never flagged. This is part of the
+/// strict rules only.
+pub struct UnpinnedPackage;
+
+impl LintRule for UnpinnedPackage {
+    fn id(&self) -> &'static str {
+        "UnpinnedPackage"
+    }
+
+    fn description(&self) -> &'static str {
+        "Installed packages should be pinned to a version"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for run in package_runs(dockerfile) {
+            for command in &run.commands {
+                let pin = match (command.manager, command.subcommand.as_str()) {
+                    (Manager::Apt, "install") => "package=version",
+                    (Manager::Apk, "add") => "package=version",
+                    (Manager::Yum, "install") => "package-version",
+                    (Manager::Pip, "install") => "package==version",
+                    (Manager::Npm, "install") => "package@version",
+                    _ => continue,
+                };
+                for (package, name) in command.packages() {
+                    let pinned = match command.manager {
+                        Manager::Apt | Manager::Apk => name.contains(['=', '<', '>', '~']),
+                        Manager::Yum => name.split('-').skip(1).any(|part| part.starts_with(|c: char| c.is_ascii_digit())),
+                        Manager::Pip => name.contains(['=', '<', '>', '~', '@']),
+                        Manager::Npm => name.get(1..).is_some_and(|name| name.contains('@')),
+                    };
+                    let scoped = command.manager == Manager::Npm && name.starts_with('@');
+                    let unknown = name.contains('$') || name.starts_with('.') || (name.contains('/') && !scoped);
+                    if !pinned && !unknown {
+                        let message = format!("Pin the version of '{}', as in '{}'", name, pin);
+                        findings.push(Finding::new(run.index, message).with_range(range_within(run.value, package)));
+                    }
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// `apt-get upgrade` should not be run within an image, as it makes builds unreproducible and
+/// the packages of the base image are best updated by using a newer base image.
+pub struct AptUpgrade;
+
+impl LintRule for AptUpgrade {
+    fn id(&self) -> &'static str {
+        "AptUpgrade"
+    }
+
+    fn description(&self) -> &'static str {
+        "apt-get upgrade should not be run within an image"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for run in package_runs(dockerfile) {
+            for command in &run.commands {
+                if command.manager == Manager::Apt && matches!(command.subcommand.as_str(), "upgrade" | "dist-upgrade" | "full-upgrade") {
+                    let message = format!(
+                        "Avoid '{} {}', use a newer base image or pin the packages which need updating instead",
+                        command.program, command.subcommand,
+                    );
+                    findings.push(Finding::new(run.index, message).with_range(range_within(run.value, command.text)));
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// A package manager.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Manager {
+    /// `apt-get` or `apt`.
+    Apt,
+    /// `apk`.
+    Apk,
+    /// `yum`, `dnf` or `microdnf`.
+    Yum,
+    /// `pip`, `pip3` or `python -m pip`.
+    Pip,
+    /// `npm`.
+    Npm,
+}
+
+impl Manager {
+    /// The options of this package manager which take the following word as their value.
+    fn options_with_values(self) -> &'static [&'static str] {
+        match self {
+            Manager::Apt => &["-o", "-t", "-c", "--option", "--target-release", "--config-file"],
+            Manager::Apk => &["-X", "-t", "-p", "--repository", "--virtual", "--root"],
+            Manager::Yum => &["-c", "--config", "--installroot", "--releasever"],
+            Manager::Pip => &["-r", "-c", "-e", "-i", "-t", "--requirement", "--constraint", "--editable", "--index-url", "--extra-index-url", "--target", "--prefix", "--root"],
+            Manager::Npm => &["-w", "--prefix", "--workspace", "--registry"],
+        }
+    }
+}
+
+/// A single package manager command within a `RUN`.
+struct PackageCommand<'a> {
+    /// The whole text of the command.
+    text: &'a str,
+    /// The name of the package manager, as written.
+    program: String,
+    manager: Manager,
+    /// The subcommand, such as `install`, with any abbreviation expanded.
+    subcommand: String,
+    /// The text and unquoted value of each word following the subcommand.
+    args: Vec<(&'a str, String)>,
+}
+
+impl<'a> PackageCommand<'a> {
+    /// Recognize a package manager command, which may be run with `sudo`.
+    fn parse(command: &Command<'a>) -> Option<Self> {
+        let words: Vec<_> = command.words().iter()
+            .map(|word| (word.text, word.unquoted()))
+            .skip_while(|(_, word)| word == "sudo")
+            .collect();
+        let program = &words.first()?.1;
+        let (manager, program, rest) = match program.rsplit('/').next().unwrap_or(program) {
+            "apt-get" | "apt" => (Manager::Apt, program, &words[1..]),
+            "apk" => (Manager::Apk, program, &words[1..]),
+            "yum" | "dnf" | "microdnf" => (Manager::Yum, program, &words[1..]),
+            "pip" | "pip3" => (Manager::Pip, program, &words[1..]),
+            "npm" => (Manager::Npm, program, &words[1..]),
+            name if name.starts_with("python") && words.get(1).is_some_and(|(_, word)| word == "-m") => match words.get(2) {
+                Some((_, pip)) if pip == "pip" => (Manager::Pip, pip, &words[3..]),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        // Options may come before the subcommand.
+        let mut idx = 0;
+        while idx < rest.len() && rest[idx].1.starts_with('-') {
+            idx += if manager.options_with_values().contains(&rest[idx].1.as_str()) { 2 } else { 1 };
+        }
+        let subcommand = match (manager, rest.get(idx)?.1.as_str()) {
+            (Manager::Npm, "i") | (Manager::Npm, "add") => "install",
+            (_, subcommand) => subcommand,
+        };
+        let mut args = rest[..idx].to_vec();
+        args.extend_from_slice(rest.get(idx + 1..).unwrap_or(&[]));
+        Some(PackageCommand{text: command.text, program: program.clone(), manager, subcommand: subcommand.to_string(), args})
+    }
+
+    /// Whether this command is given the option, in any of its forms.
+    fn has_option(&self, option: &str) -> bool {
+        self.args.iter().any(|(_, arg)| arg == option || arg.strip_prefix(option).is_some_and(|rest| rest.starts_with('=')))
+    }
+
+    /// Whether this command is told to answer yes to any prompt.
+    fn assumes_yes(&self) -> bool {
+        self.args.iter().any(|(_, arg)| match arg.strip_prefix('-') {
+            Some(long) if long.starts_with('-') => matches!(long, "-yes" | "-assume-yes" | "-assumeyes"),
+            Some(short) => short.contains('y'),
+            None => false,
+        })
+    }
+
+    /// The text and unquoted name of the packages named by this command, skipping options and
+    /// their values.
+    fn packages(&self) -> Vec<(&'a str, &str)> {
+        let mut packages = vec![];
+        let mut args = self.args.iter();
+        while let Some((text, arg)) = args.next() {
+            if self.manager.options_with_values().contains(&arg.as_str()) {
+                args.next();
+            } else if !arg.starts_with('-') {
+                packages.push((*text, arg.as_str()));
+            }
+        }
+        packages
+    }
+}
+
+/// A shell form `RUN` containing package manager commands.
+struct PackageRun<'a> {
+    /// The index of the `RUN` within its Dockerfile.
+    index: usize,
+    /// The arguments of the `RUN`.
+    value: &'a str,
+    /// The unquoted words of every simple command of the script.
+    words: Vec<Vec<String>>,
+    commands: Vec<PackageCommand<'a>>,
+}
+
+impl PackageRun<'_> {
+    /// Whether the script runs `rm` upon something within the given directory.
+    fn removes(&self, dir: &str) -> bool {
+        self.words.iter()
+            .filter(|words| words.first().is_some_and(|name| name == "rm"))
+            .any(|words| words[1..].iter().any(|word| word.starts_with(dir)))
+    }
+
+    /// Whether the script runs the given program's `cache clean` or `clean` subcommand with the
+    /// given argument.
+    fn cleans(&self, program: &str, arg: &str) -> bool {
+        self.words.iter().any(|words| {
+            words.first().is_some_and(|name| name == program)
+                && words.iter().any(|word| word == "clean")
+                && words.iter().any(|word| word == arg || (arg == "clean" && word == "cache"))
+        })
+    }
+}
+
+/// Find every shell form `RUN` of the Dockerfile which runs a package manager.
+fn package_runs(dockerfile: &Dockerfile) -> Vec<PackageRun<'_>> {
+    let mut runs = vec![];
+    for (index, script) in run_scripts(dockerfile) {
+        let mut words = vec![];
+        let mut commands = vec![];
+        for command in script.commands() {
+            commands.extend(PackageCommand::parse(command));
+            words.push(command.words().iter().map(Word::unquoted).collect());
+        }
+        if !commands.is_empty() {
+            runs.push(PackageRun{index, value: dockerfile.instructions()[index].value(), words, commands});
+        }
+    }
+    runs
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lint::{
+        check,
+        messages,
+    };
+
+    #[test]
+    fn package_manager_yes() {
+        let input = r##"RUN apt-get update && apt-get install curl
+RUN apt-get install -qy curl
+RUN dnf --assumeyes install git && yum remove git
+RUN if [ -n "$DEV" ]; then sudo "apt-get" install gdb; fi
+RUN echo "apt-get install curl"
+"##;
+        assert_eq!(messages(PackageManagerYes, input), vec![
+            "Use 'apt-get install -y' to avoid prompting for confirmation",
+            "Use 'yum remove -y' to avoid prompting for confirmation",
+            "Use 'apt-get install -y' to avoid prompting for confirmation",
+        ]);
+    }
+
+    #[test]
+    fn apt_no_install_recommends() {
+        let input = "RUN apt-get install -y curl\nRUN apt-get install -y --no-install-recommends curl\nRUN apt-get -o APT::Install-Recommends=false install -y git\n";
+        assert_eq!(messages(AptNoInstallRecommends, input), vec![
+            "Use 'apt-get install --no-install-recommends' to avoid installing recommended packages",
+        ]);
+    }
+
+    #[test]
+    fn package_cache_cleanup() {
+        let input = r##"RUN apt-get update && apt-get install -y curl
+RUN apt-get update && apt-get install -y curl && rm -rf /var/lib/apt/lists/*
+RUN --mount=type=cache,target=/var/cache/apt apt-get install -y curl
+RUN apk add curl && apk add --no-cache git
+RUN dnf install -y git && dnf clean all
+RUN python3 -m pip install requests; npm ci
+"##;
+        assert_eq!(messages(PackageCacheCleanup, input), vec![
+            "'apt-get install' leaves its cache in the layer, remove /var/lib/apt/lists/* after installing packages",
+            "'apk add' leaves its cache in the layer, use 'apk add --no-cache'",
+            "'pip install' leaves its cache in the layer, use 'pip install --no-cache-dir'",
+            "'npm ci' leaves its cache in the layer, run 'npm cache clean --force' after installing packages",
+        ]);
+    }
+
+    #[test]
+    fn unpinned_package() {
+        let input = r##"RUN apt-get install -y curl=7.88.1-10 git "vim"
+RUN apk add --virtual .build gcc musl-dev=1.2.4-r2
+RUN yum install -y httpd-2.4.57 ${PACKAGES} ./local.rpm
+RUN pip install -r requirements.txt flask==3.0.0 django
+RUN npm install -g @angular/cli@17.0.0 @types/node typescript && npm install
+"##;
+        assert_eq!(messages(UnpinnedPackage, input), vec![
+            "Pin the version of 'git', as in 'package=version'",
+            "Pin the version of 'vim', as in 'package=version'",
+            "Pin the version of 'gcc', as in 'package=version'",
+            "Pin the version of 'django', as in 'package==version'",
+            "Pin the version of '@types/node', as in 'package@version'",
+            "Pin the version of 'typescript', as in 'package@version'",
+        ]);
+    }
+
+    #[test]
+    fn apt_upgrade() {
+        assert_eq!(check(AptUpgrade, "RUN apt-get update && DEBIAN_FRONTEND=noninteractive apt-get -y dist-upgrade\n"), vec![
+            Finding::new(1, "Avoid 'apt-get dist-upgrade', use a newer base image or pin the packages which need updating instead").with_range(18..72),
+        ]);
+    }
+}
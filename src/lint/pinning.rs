@@ -0,0 +1,198 @@
+use crate::{
+    builder::Dockerfile,
+    image::ImageRef,
+    instructions::Instruction,
+    lint::{
+        Finding,
+        LintRule,
+    },
+    stage::{
+        self,
+        range_within,
+        EdgeKind,
+    },
+};
+
+/// Base images should be given a tag, rather than implicitly using `latest`.
+pub struct UntaggedImage;
+
+impl LintRule for UntaggedImage {
+    fn id(&self) -> &'static str {
+        "UntaggedImage"
+    }
+
+    fn description(&self) -> &'static str {
+        "Base images should have a tag"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        pulled_images(dockerfile).into_iter()
+            .filter(|(_, _, image)| image.tag.is_none() && image.digest.is_none() && !image.is_interpolated())
+            .map(|(idx, value, image)| {
+                let message = format!("Image '{}' has no tag, so it implicitly uses 'latest'", image.name);
+                Finding::new(idx, message).with_range(range_within(value, image.name))
+            })
+            .collect()
+    }
+}
+
+/// Base images should not use the `latest` tag, which changes from one build to the next.
+pub struct LatestTag;
+
+impl LintRule for LatestTag {
+    fn id(&self) -> &'static str {
+        "LatestTag"
+    }
+
+    fn description(&self) -> &'static str {
+        "Base images should not use the latest tag"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, value, image) in pulled_images(dockerfile) {
+            if let (Some(tag @ "latest"), None) = (image.tag, image.digest) {
+                let message = format!("Image '{}' uses the 'latest' tag, which changes over time", image.name);
+                findings.push(Finding::new(idx, message).with_range(range_within(value, tag)));
+            }
+        }
+        findings
+    }
+}
+
+/// Base images should not use a tag naming only a major version, such as `node:20` or
+/// `python:3-slim`, which moves with every minor release.
+///
+/// Only versions of one or two digits are taken to be major versions, so that tags made of a
+/// date or a build number, such as `20240101`, are not flagged.
+pub struct FloatingMajorTag;
+
+impl LintRule for FloatingMajorTag {
+    fn id(&self) -> &'static str {
+        "FloatingMajorTag"
+    }
+
+    fn description(&self) -> &'static str {
+        "Base images should pin more than a major version"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, value, image) in pulled_images(dockerfile) {
+            let tag = match (image.tag, image.digest) {
+                (Some(tag), None) if !tag.contains('$') => tag,
+                _ => continue,
+            };
+            let version = tag.split('-').next().unwrap_or(tag);
+            let version = version.strip_prefix('v').unwrap_or(version);
+            if (1..=2).contains(&version.len()) && version.chars().all(|c| c.is_ascii_digit()) {
+                let message = format!("Image '{}:{}' uses a floating major version tag, pin at least the minor version", image.name, tag);
+                findings.push(Finding::new(idx, message).with_range(range_within(value, tag)));
+            }
+        }
+        findings
+    }
+}
+
+/// Base images should be pinned to a digest, so that every build uses exactly the same image.
+///
+/// This is part of the strict rules only.
+pub struct MissingDigest;
+
+impl LintRule for MissingDigest {
+    fn id(&self) -> &'static str {
+        "MissingDigest"
+    }
+
+    fn description(&self) -> &'static str {
+        "Base images should be pinned to a digest"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, value, image) in pulled_images(dockerfile) {
+            // An interpolated name may be given a digest along with it.
+            if image.digest.is_none() && !image.name.contains('$') {
+                let reference = stage::split_from(value).0;
+                let message = format!("Image '{}' is not pinned to a digest with @sha256:", reference);
+                findings.push(Finding::new(idx, message).with_range(range_within(value, reference)));
+            }
+        }
+        findings
+    }
+}
+
+/// The base images which are pulled from a registry, along with the index and arguments of their
+/// `FROM` instructions. References to earlier stages and `scratch` are skipped.
+fn pulled_images(dockerfile: &Dockerfile) -> Vec<(usize, &str, ImageRef<'_>)> {
+    let stages = dockerfile.stages();
+    let mut images = vec![];
+    for stage in &stages {
+        let idx = stage.range().start;
+        let from = match &dockerfile.instructions()[idx] {
+            Instruction::From(from) => from,
+            _ => continue,
+        };
+        let image = from.image();
+        let is_stage = matches!(stage::resolve(&stages[..stage.index()], stage.base(), EdgeKind::From), Some(Ok(_)));
+        if !is_stage && !image.is_scratch() {
+            images.push((idx, dockerfile.instructions()[idx].value(), image));
+        }
+    }
+    images
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lint::{
+        check,
+        findings,
+    };
+
+    const IMAGES: &str = r##"FROM alpine AS base
+FROM base AS derived
+FROM scratch
+FROM ubuntu:latest
+FROM node:20-alpine
+FROM python:v3
+FROM node:${NODE}
+FROM ${BASE_IMAGE}
+FROM localhost:5000/app
+FROM rust:1.75
+FROM debian:latest@sha256:abc
+FROM myimage:20240101
+FROM app:2024-01-01
+FROM app:v345
+"##;
+
+    #[test]
+    fn untagged_image() {
+        assert_eq!(findings(UntaggedImage, IMAGES), vec![
+            (1, "Image 'alpine' has no tag, so it implicitly uses 'latest'".to_string()),
+            (9, "Image 'localhost:5000/app' has no tag, so it implicitly uses 'latest'".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn latest_tag() {
+        assert_eq!(check(LatestTag, IMAGES), vec![Finding::new(4, "Image 'ubuntu' uses the 'latest' tag, which changes over time").with_range(7..13)]);
+    }
+
+    #[test]
+    fn floating_major_tag() {
+        assert_eq!(findings(FloatingMajorTag, IMAGES), vec![
+            (5, "Image 'node:20-alpine' uses a floating major version tag, pin at least the minor version".to_string()),
+            (6, "Image 'python:v3' uses a floating major version tag, pin at least the minor version".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn missing_digest() {
+        let indices: Vec<_> = findings(MissingDigest, IMAGES).into_iter().map(|(idx, _)| idx).collect();
+        assert_eq!(indices, vec![0, 1, 4, 5, 6, 7, 9, 10, 12, 13, 14]);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    builder::Dockerfile,
+    form::Form,
+    instructions::Instruction,
+    lint::{
+        Finding,
+        LintRule,
+    },
+    stage::{
+        range_within,
+        split_from,
+    },
+};
+
+/// `CMD` and `ENTRYPOINT` should use the JSON form, so that the process receives signals
+/// directly rather than through a shell.
+pub struct JsonArgsRecommended;
+
+impl LintRule for JsonArgsRecommended {
+    fn id(&self) -> &'static str {
+        "JSONArgsRecommended"
+    }
+
+    fn description(&self) -> &'static str {
+        "CMD and ENTRYPOINT should use the JSON form"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for (idx, instruction) in dockerfile.instructions().iter().enumerate() {
+            if !matches!(instruction, Instruction::Cmd(_) | Instruction::Entrypoint(_)) {
+                continue;
+            }
+            if let Some(Form::Shell(_)) = instruction.form() {
+                let message = format!(
+                    "JSON arguments recommended for {} to prevent unintended behavior related to OS signals",
+                    instruction.keyword(),
+                );
+                findings.push(Finding::new(idx, message));
+            }
+        }
+        findings
+    }
+}
+
+/// Stage names should be lowercase.
+pub struct StageNameCasing;
+
+impl LintRule for StageNameCasing {
+    fn id(&self) -> &'static str {
+        "StageNameCasing"
+    }
+
+    fn description(&self) -> &'static str {
+        "Stage names should be lowercase"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for stage in dockerfile.stages() {
+            let value = dockerfile.instructions()[stage.range().start].value();
+            if let Some(name) = split_from(value).1 {
+                if name.chars().any(|c| c.is_ascii_uppercase()) {
+                    let finding = Finding::new(stage.range().start, format!("Stage name '{}' should be lowercase", name));
+                    findings.push(finding.with_range(range_within(value, name)));
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// A relative `WORKDIR` should follow an absolute one within the same stage, as otherwise it
+/// depends upon the working directory of the base image.
+pub struct WorkdirRelativePath;
+
+impl LintRule for WorkdirRelativePath {
+    fn id(&self) -> &'static str {
+        "WorkdirRelativePath"
+    }
+
+    fn description(&self) -> &'static str {
+        "Relative WORKDIR paths should follow an absolute WORKDIR"
+    }
+
+    fn check(&self, dockerfile: &Dockerfile) -> Vec<Finding> {
+        let mut findings = vec![];
+        for stage in dockerfile.stages() {
+            let mut absolute = false;
+            for idx in stage.range() {
+                let instruction = &dockerfile.instructions()[idx];
+                if !matches!(instruction, Instruction::Workdir(_)) {
+                    continue;
+                }
+                let path = instruction.value().trim();
+                // Paths from variables cannot be known, and Windows paths begin with a drive.
+                if path.starts_with(['/', '$']) || path.get(1..2) == Some(":") {
+                    absolute = true;
+                } else if !absolute {
+                    let message = format!(
+                        "Relative workdir {:?} can have unexpected results if the base image changes",
+                        path,
+                    );
+                    findings.push(Finding::new(idx, message));
+                }
+            }
+        }
+        findings
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lint::findings;
+
+    #[test]
+    fn json_args_recommended() {
+        assert_eq!(findings(JsonArgsRecommended, "CMD [\"run\"]\nENTRYPOINT run\nRUN make\n"), vec![
+            (2, "JSON arguments recommended for ENTRYPOINT to prevent unintended behavior related to OS signals".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn stage_name_casing() {
+        let input = "FROM alpine AS build\nFROM alpine AS Release\n";
+        assert_eq!(findings(StageNameCasing, input), vec![(2, "Stage name 'Release' should be lowercase".to_string())]);
+    }
+
+    #[test]
+    fn workdir_relative_path() {
+        let input = "WORKDIR app\nWORKDIR /app\nWORKDIR src\nFROM alpine\nWORKDIR $HOME\nWORKDIR src\n";
+        assert_eq!(findings(WorkdirRelativePath, input), vec![
+            (1, "Relative workdir \"app\" can have unexpected results if the base image changes".to_string()),
+        ]);
+    }
+}
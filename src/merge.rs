@@ -0,0 +1,282 @@
+use std::{
+    cell::RefCell,
+    error,
+    fmt,
+    ops::Range,
+};
+
+use crate::{
+    builder::Dockerfile,
+    instructions::{
+        Arg,
+        From,
+        Instruction,
+    },
+    render::{
+        self,
+        split_words,
+    },
+    stage::{
+        self,
+        is_valid_stage_name,
+        split_from,
+        EdgeKind,
+    },
+};
+
+/// Options controlling how `Dockerfile::merge` combines two Dockerfiles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeOptions {
+    /// The prefix given to the name of every imported stage, so that `builder` becomes
+    /// `imported-builder` by default.
+    pub prefix: String,
+    /// Whether an image referenced by the imported Dockerfile may refer to a stage of the same
+    /// name once merged, such as `COPY --from=builder` where only this Dockerfile has a `builder`
+    /// stage. This is off by default, making such a reference a `MergeError::ImageIsStage`.
+    pub bind_images: bool,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions{prefix: "imported-".to_string(), bind_images: false}
+    }
+}
+
+/// The reasons two Dockerfiles could not be merged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeError {
+    /// Both Dockerfiles give the parser directive of the given name, with different values.
+    ConflictingDirective(String),
+    /// Both Dockerfiles declare the global argument of the given name, with different defaults.
+    ConflictingArg {
+        name: String,
+        ours: Option<String>,
+        theirs: Option<String>,
+    },
+    /// The namespaced name of an imported stage is already taken, or is not a valid stage name.
+    StageName(String),
+    /// An image referenced by the imported Dockerfile would refer to the stage of the same name
+    /// once merged.
+    ImageIsStage(String),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::ConflictingDirective(name) => write!(f, "conflicting values for parser directive {}", name),
+            MergeError::ConflictingArg{name, ours, theirs} => write!(
+                f, "conflicting defaults for global ARG {}: {} and {}",
+                name, ours.as_deref().unwrap_or("(none)"), theirs.as_deref().unwrap_or("(none)"),
+            ),
+            MergeError::StageName(name) => write!(f, "cannot import stage as {}", name),
+            MergeError::ImageIsStage(name) => write!(f, "imported image {} would refer to the stage of that name", name),
+        }
+    }
+}
+
+impl error::Error for MergeError {}
+
+impl Dockerfile {
+    /// Merge the stages of another Dockerfile into this one, producing a single multi-stage file.
+    ///
+    /// The imported stages follow this Dockerfile's stages, and their names are namespaced with
+    /// `MergeOptions::prefix`. References between the imported stages are rewritten to match,
+    /// including numeric `--from` indices. Parser directives and global `ARG`s are reconciled,
+    /// failing when both Dockerfiles give the same one different values. An imported reference to
+    /// an image which shares its name with a stage of the merged file is an error, unless
+    /// `MergeOptions::bind_images` is set.
+    pub fn merge(&self, other: &Dockerfile, options: &MergeOptions) -> Result<Dockerfile, MergeError> {
+        let (ours, theirs) = (self.stages(), other.stages());
+        let our_preamble = ours.first().map_or(self.instructions().len(), |stage| stage.range().start);
+        let their_preamble = theirs.first().map_or(other.instructions().len(), |stage| stage.range().start);
+        let escape = render::escape_char(other);
+        if render::escape_char(self) != escape {
+            return Err(MergeError::ConflictingDirective("escape".to_string()));
+        }
+
+        // Reconcile the parser directives and global arguments.
+        let (mut directives, mut globals) = (vec![], vec![]);
+        let (our_args, their_args) = (self.global_args(), other.global_args());
+        for (idx, instruction) in other.instructions()[..their_preamble].iter().enumerate() {
+            match instruction {
+                Instruction::Directive(_) => {
+                    let name = directive_name(instruction.value());
+                    let existing = self.instructions()[..our_preamble].iter()
+                        .filter(|inst| matches!(inst, Instruction::Directive(_)))
+                        .find(|inst| directive_name(inst.value()).eq_ignore_ascii_case(name));
+                    match existing {
+                        Some(existing) if existing.value().trim() != instruction.value().trim() => {
+                            return Err(MergeError::ConflictingDirective(name.to_ascii_lowercase()));
+                        }
+                        Some(_) => (),
+                        None => directives.push(idx),
+                    }
+                }
+                Instruction::Arg(_) => {
+                    let mut words = vec![];
+                    for (_, word) in split_words(instruction.value(), escape) {
+                        let name = word.split('=').next().unwrap_or(word);
+                        let theirs = their_args.iter().find(|decl| decl.index == idx && decl.name == name).expect("every word of an ARG declares an argument");
+                        match our_args.iter().rev().find(|decl| decl.name == name) {
+                            Some(ours) if ours.default != theirs.default => return Err(MergeError::ConflictingArg{
+                                name: name.to_string(),
+                                ours: ours.default.map(str::to_string),
+                                theirs: theirs.default.map(str::to_string),
+                            }),
+                            Some(_) => (),
+                            None => words.push(word),
+                        }
+                    }
+                    if !words.is_empty() {
+                        globals.push((Instruction::Arg(Arg::new(words.join(" "))), other.provenance(idx)));
+                    }
+                }
+                _ => globals.push((instruction.clone(), other.provenance(idx))),
+            }
+        }
+
+        // Namespace the imported stages, checking the new names are free.
+        let mut names = vec![None; theirs.len()];
+        for stage in &theirs {
+            if let Some(name) = stage.name() {
+                let namespaced = format!("{}{}", options.prefix, name);
+                let taken = ours.iter().any(|ours| ours.name().is_some_and(|ours| ours.eq_ignore_ascii_case(&namespaced)));
+                if taken || !is_valid_stage_name(&namespaced) {
+                    return Err(MergeError::StageName(namespaced));
+                }
+                names[stage.index()] = Some(namespaced);
+            }
+        }
+
+        // Import the stages after our own, rewriting their references to one another.
+        let offset = ours.len();
+        let is_stage = |reference: &str| {
+            ours.iter().filter_map(|stage| stage.name()).chain(names.iter().flatten().map(String::as_str))
+                .any(|name| name.eq_ignore_ascii_case(reference))
+        };
+        let clash = RefCell::new(None);
+        let rewrite = |kind: EdgeKind, reference: &str| match stage::resolve(&theirs, reference, kind) {
+            Some(Ok(target)) => match (&names[target], kind) {
+                (Some(name), _) => Some(name.clone()),
+                (None, EdgeKind::Copy) | (None, EdgeKind::Mount) => Some((target + offset).to_string()),
+                (None, EdgeKind::From) => None,
+            },
+            None if !options.bind_images && is_stage(reference) => {
+                clash.borrow_mut().get_or_insert_with(|| reference.to_string());
+                None
+            }
+            _ => None,
+        };
+        let mut stages = vec![];
+        for stage in &theirs {
+            for idx in stage.range() {
+                let instruction = &other.instructions()[idx];
+                let mut imported = stage::rewrite_references(instruction, escape, rewrite);
+                if let (Instruction::From(_), Some(name)) = (instruction, &names[stage.index()]) {
+                    let value = imported.value().to_string();
+                    let alias = split_from(&value).1.expect("the stage has a name");
+                    imported = Instruction::From(From::new(stage::replace_within(&value, alias, name)));
+                }
+                stages.push((imported, other.provenance(idx)));
+            }
+        }
+        if let Some(reference) = clash.into_inner() {
+            return Err(MergeError::ImageIsStage(reference));
+        }
+
+        let our_directives = self.instructions()[..our_preamble].iter().take_while(|inst| matches!(inst, Instruction::Directive(_))).count();
+        let ours = |range: Range<usize>| range.map(|idx| (self.instructions()[idx].clone(), self.provenance(idx)));
+        let (instructions, provenance) = ours(0..our_directives)
+            .chain(directives.into_iter().map(|idx| (other.instructions()[idx].clone(), other.provenance(idx))))
+            .chain(ours(our_directives..our_preamble))
+            .chain(globals)
+            .chain(ours(our_preamble..self.instructions().len()))
+            .chain(stages)
+            .unzip();
+        // The lines of two different files mean nothing in the merged one.
+        Ok(Dockerfile{instructions, provenance, lines: vec![]})
+    }
+}
+
+/// The name of a parser directive, such as `syntax` for `syntax=docker/dockerfile:1`.
+fn directive_name(directive: &str) -> &str {
+    directive.split('=').next().unwrap_or("").trim()
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BUILD: &str = r##"# syntax=docker/dockerfile:1
+ARG RUST_VERSION=1.75
+FROM rust:${RUST_VERSION} AS builder
+RUN cargo build --release
+"##;
+
+    const RUNTIME: &str = r##"# syntax=docker/dockerfile:1
+# check=error=true
+ARG RUST_VERSION=1.75 DEBIAN=bookworm
+FROM debian:${DEBIAN}-slim AS base
+RUN useradd app
+FROM alpine
+RUN apk add ca-certificates
+FROM base AS runtime
+COPY --from=1 /etc/ssl /etc/ssl
+RUN --mount=type=bind,from=base,target=/base ls /base
+COPY --from=builder /app/target/release/app /usr/local/bin/app
+"##;
+
+    #[test]
+    fn merge_namespaces_imported_stages() {
+        let build = Dockerfile::parse(BUILD).unwrap();
+        let runtime = Dockerfile::parse(RUNTIME).unwrap();
+        assert_eq!(
+            build.merge(&runtime, &MergeOptions{prefix: "rt-".into(), bind_images: false}),
+            Err(MergeError::ImageIsStage("builder".into())),
+        );
+        let merged = build.merge(&runtime, &MergeOptions{prefix: "rt-".into(), bind_images: true}).unwrap();
+        assert_eq!(merged.to_string(), r##"# syntax=docker/dockerfile:1
+# check=error=true
+ARG RUST_VERSION=1.75
+ARG DEBIAN=bookworm
+FROM rust:${RUST_VERSION} AS builder
+RUN cargo build --release
+FROM debian:${DEBIAN}-slim AS rt-base
+RUN useradd app
+FROM alpine
+RUN apk add ca-certificates
+FROM rt-base AS rt-runtime
+COPY --from=2 /etc/ssl /etc/ssl
+RUN --mount=type=bind,from=rt-base,target=/base ls /base
+COPY --from=builder /app/target/release/app /usr/local/bin/app
+"##);
+        assert_eq!(merged.check_stage_refs(), Ok(()));
+        assert_eq!(merged.stage_graph().dependencies(3), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn merge_conflicts() {
+        let merge = |ours: &str, theirs: &str| {
+            Dockerfile::parse(ours).unwrap().merge(&Dockerfile::parse(theirs).unwrap(), &MergeOptions::default())
+        };
+        assert_eq!(merge("ARG A=1\nFROM alpine\n", "ARG A=1\nFROM alpine\n").map(|d| d.to_string()), Ok("ARG A=1\nFROM alpine\nFROM alpine\n".into()));
+        assert_eq!(
+            merge("ARG A=1\nFROM alpine\n", "ARG A=2\nFROM alpine\n"),
+            Err(MergeError::ConflictingArg{name: "A".into(), ours: Some("1".into()), theirs: Some("2".into())}),
+        );
+        assert_eq!(
+            merge("# syntax=docker/dockerfile:1\nFROM alpine\n", "# syntax=docker/dockerfile:1.4\nFROM alpine\n"),
+            Err(MergeError::ConflictingDirective("syntax".into())),
+        );
+        assert_eq!(merge("# escape=`\nFROM alpine\n", "FROM alpine\n"), Err(MergeError::ConflictingDirective("escape".into())));
+        assert_eq!(
+            merge("FROM alpine AS imported-a\n", "FROM alpine AS a\n"),
+            Err(MergeError::StageName("imported-a".into())),
+        );
+        assert_eq!(merge("FROM alpine AS base\n", "FROM BASE\n"), Err(MergeError::ImageIsStage("BASE".into())));
+        assert_eq!(merge("FROM alpine\n", "FROM alpine AS a\nFROM imported-a\n"), Err(MergeError::ImageIsStage("imported-a".into())));
+    }
+}
@@ -0,0 +1,687 @@
+use std::{
+    error,
+    fmt,
+    str,
+    io::{
+        self,
+        BufRead,
+    },
+};
+
+use crate::{
+    builder::Dockerfile,
+    diagnostic::Diagnostic,
+    form::Form,
+    flags::{
+        FlagError,
+        Flags,
+    },
+    instructions::{
+        Add,
+        Arg,
+        Cmd,
+        Copy,
+        Directive,
+        Entrypoint,
+        Env,
+        Expose,
+        From,
+        Healthcheck,
+        Instruction,
+        Label,
+        Onbuild,
+        Run,
+        Shell,
+        Stopsignal,
+        User,
+        Volume,
+        Workdir,
+    },
+};
+
+/// The parser directives which Docker recognizes at the top of a Dockerfile.
+const KNOWN_DIRECTIVES: &[&str] = &["syntax", "escape", "check"];
+
+/// The instructions which accept a JSON array as their arguments.
+const JSON_INSTRUCTIONS: &[&str] = &["ADD", "CMD", "COPY", "ENTRYPOINT", "RUN", "SHELL", "VOLUME"];
+
+/// An error encountered while parsing a Dockerfile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    line: usize,
+    kind: ParseErrorKind,
+}
+
+/// The different kinds of errors which may be encountered while parsing a Dockerfile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The line began with a keyword which is not a known Dockerfile instruction.
+    UnknownInstruction(String),
+
+    /// The named instruction was given no arguments.
+    MissingArguments(String),
+
+    /// The flags given to an instruction were malformed or are not accepted by the instruction.
+    Flag(FlagError),
+
+    /// The arguments were a valid JSON array, but not every element of it was a string.
+    NotStringArray,
+
+    /// The named instruction only accepts its arguments as a JSON array.
+    JsonRequired(String),
+
+    /// The input did not hold an instruction of the expected kind.
+    UnexpectedInstruction {
+        expected: ExpectedInstruction,
+        /// The keyword of the instruction found, if any, which is `#` for a parser directive.
+        found: Option<&'static str>,
+    },
+
+    /// The input held more than the single instruction which was expected.
+    TrailingInput,
+
+    /// The `escape` parser directive named something other than `\` or `` ` ``.
+    InvalidEscape(String),
+
+    /// The underlying reader failed while reading the given line.
+    Io {
+        kind: io::ErrorKind,
+        message: String,
+    },
+}
+
+/// The kind of instruction which was expected, as reported by
+/// `ParseErrorKind::UnexpectedInstruction`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpectedInstruction {
+    /// Any instruction at all.
+    Any,
+    /// An instruction with the given keyword, such as `COPY`.
+    Keyword(&'static str),
+    /// A parser directive.
+    Directive,
+}
+
+impl ExpectedInstruction {
+    /// Whether the given instruction is of the expected kind.
+    fn matches(&self, instruction: &Instruction) -> bool {
+        match self {
+            ExpectedInstruction::Any => true,
+            ExpectedInstruction::Keyword(keyword) => !matches!(instruction, Instruction::Directive(_)) && instruction.keyword() == *keyword,
+            ExpectedInstruction::Directive => matches!(instruction, Instruction::Directive(_)),
+        }
+    }
+}
+
+impl fmt::Display for ExpectedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpectedInstruction::Any => write!(f, "an instruction"),
+            ExpectedInstruction::Keyword(keyword) => write!(f, "{} instruction", keyword),
+            ExpectedInstruction::Directive => write!(f, "a parser directive"),
+        }
+    }
+}
+
+impl ParseError {
+    pub(crate) fn new(line: usize, kind: ParseErrorKind) -> Self {
+        ParseError{line, kind}
+    }
+
+    /// The 1-based line number on which the offending instruction begins.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The kind of error which was encountered.
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnknownInstruction(keyword) => write!(f, "unknown instruction: {}", keyword),
+            ParseErrorKind::MissingArguments(keyword) => write!(f, "{} requires at least one argument", keyword),
+            ParseErrorKind::Flag(err) => write!(f, "{}", err),
+            ParseErrorKind::NotStringArray => write!(f, "when using JSON array syntax, arrays must be comprised of strings only"),
+            ParseErrorKind::JsonRequired(keyword) => write!(f, "{} requires the arguments to be in JSON form", keyword),
+            ParseErrorKind::UnexpectedInstruction{expected, found: Some("#")} => write!(f, "expected {}, found a parser directive", expected),
+            ParseErrorKind::UnexpectedInstruction{expected, found: Some(found)} => write!(f, "expected {}, found {}", expected, found),
+            ParseErrorKind::UnexpectedInstruction{expected, found: None} => write!(f, "expected {}, found nothing", expected),
+            ParseErrorKind::TrailingInput => write!(f, "expected a single instruction"),
+            ParseErrorKind::InvalidEscape(escape) => write!(f, "invalid escape token '{}' does not match ` or \\", escape),
+            ParseErrorKind::Io{message, ..} => write!(f, "I/O error: {}", message),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl std::convert::From<ParseError> for Diagnostic {
+    fn from(err: ParseError) -> Self {
+        Diagnostic::error(err.line, err.kind.to_string())
+    }
+}
+
+impl Dockerfile {
+    /// Parse a Dockerfile from its textual form.
+    ///
+    /// Parsing is strict: the first problem encountered is returned as an error, while warnings
+    /// are ignored. Comments which are not parser directives are discarded.
+    pub fn parse(input: &str) -> Result<Dockerfile, ParseError> {
+        parse_with(input, Err, |_| ())
+    }
+
+    /// Parse a Dockerfile from its textual form, recovering from any errors along the way.
+    ///
+    /// This always produces a best-effort Dockerfile, which makes it suitable for editor tooling
+    /// operating on half-written files. Lines with unknown instructions are skipped, while
+    /// instructions with malformed flags or arguments are kept verbatim. Every problem
+    /// encountered is reported as a diagnostic, including warnings such as arguments which look
+    /// like a JSON array but will be treated as shell form.
+    pub fn parse_tolerant(input: &str) -> (Dockerfile, Vec<Diagnostic>) {
+        let diagnostics = std::cell::RefCell::new(vec![]);
+        let dockerfile = parse_with(input,
+            |err| {
+                diagnostics.borrow_mut().push(err.into());
+                Ok(())
+            },
+            |warning| diagnostics.borrow_mut().push(warning),
+        ).unwrap_or_else(|_| Dockerfile::from_instructions(vec![]));
+        (dockerfile, diagnostics.into_inner())
+    }
+}
+
+impl str::FromStr for Dockerfile {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Dockerfile::parse(input)
+    }
+}
+
+/// Parse input holding exactly one instruction, which must be of the expected kind.
+pub(crate) fn parse_single(input: &str, expected: ExpectedInstruction) -> Result<Instruction, ParseError> {
+    let mut lines = LogicalLines::new(input.lines().map(Ok::<_, io::Error>));
+    let logical = match lines.next() {
+        Some(logical) => logical?,
+        None => {
+            let kind = ParseErrorKind::UnexpectedInstruction{expected, found: None};
+            return Err(ParseError::new(1, kind));
+        }
+    };
+    if let Some(extra) = lines.next() {
+        return Err(ParseError::new(extra?.line, ParseErrorKind::TrailingInput));
+    }
+
+    let line = logical.line;
+    let parsed = logical.parse();
+    if let Some(err) = parsed.errors.into_iter().next() {
+        return Err(err);
+    }
+    let instruction = parsed.instruction.expect("an instruction is always built when there are no errors");
+    if !expected.matches(&instruction) {
+        let kind = ParseErrorKind::UnexpectedInstruction{expected, found: Some(instruction.keyword())};
+        return Err(ParseError::new(line, kind));
+    }
+    Ok(instruction)
+}
+
+/// Parse the given input, passing every error to `on_error` and every warning to `on_warning`.
+///
+/// Parsing is aborted as soon as `on_error` returns an error.
+fn parse_with<E, W>(input: &str, mut on_error: E, mut on_warning: W) -> Result<Dockerfile, ParseError>
+    where E: FnMut(ParseError) -> Result<(), ParseError>, W: FnMut(Diagnostic),
+{
+    let (mut instructions, mut lines) = (vec![], vec![]);
+    for logical in LogicalLines::new(input.lines().map(Ok::<_, io::Error>)) {
+        let logical = logical?;
+        let line = logical.line;
+        let parsed = logical.parse();
+        for err in parsed.errors {
+            on_error(err)?;
+        }
+        parsed.warnings.into_iter().for_each(&mut on_warning);
+        if let Some(instruction) = parsed.instruction {
+            instructions.push(instruction);
+            lines.push(Some(line));
+        }
+    }
+    let provenance = vec![None; instructions.len()];
+    Ok(Dockerfile{instructions, provenance, lines})
+}
+
+/// The outcome of parsing a single logical line.
+#[derive(Default)]
+struct Parsed {
+    /// The best-effort instruction, if one could be built.
+    instruction: Option<Instruction>,
+    /// Any errors found in the line.
+    errors: Vec<ParseError>,
+    /// Any warnings about the line, which do not prevent Docker from accepting it.
+    warnings: Vec<Diagnostic>,
+}
+
+/// Parse a single logical line into an instruction.
+fn parse_instruction(line: usize, text: &str) -> Parsed {
+    let text = text.trim();
+    let (keyword, args) = match text.find(char::is_whitespace) {
+        Some(idx) => (&text[..idx], text[idx..].trim()),
+        None => (text, ""),
+    };
+    let keyword = keyword.to_ascii_uppercase();
+
+    let mut parsed = Parsed::default();
+    let errors = &mut parsed.errors;
+    let instruction = match instruction_for(&keyword, args.to_string()) {
+        Some(instruction) => instruction,
+        None => {
+            errors.push(ParseError::new(line, ParseErrorKind::UnknownInstruction(keyword)));
+            return parsed;
+        }
+    };
+
+    if args.is_empty() {
+        errors.push(ParseError::new(line, ParseErrorKind::MissingArguments(keyword.clone())));
+    }
+
+    let rest = match Flags::parse(args) {
+        Ok((flags, rest)) => {
+            if let Err(err) = flags.check(instruction.accepted_flags()) {
+                errors.push(ParseError::new(line, ParseErrorKind::Flag(err)));
+            }
+            rest
+        }
+        Err(err) => {
+            errors.push(ParseError::new(line, ParseErrorKind::Flag(err)));
+            args
+        }
+    };
+
+    if JSON_INSTRUCTIONS.contains(&keyword.as_str()) && !rest.is_empty() {
+        match Form::detect(rest) {
+            None => errors.push(ParseError::new(line, ParseErrorKind::NotStringArray)),
+            Some(Form::Shell(_)) if keyword == "SHELL" => {
+                errors.push(ParseError::new(line, ParseErrorKind::JsonRequired(keyword)));
+            }
+            Some(form) => if form.fell_back_from_json() {
+                let message = format!("{} arguments look like a JSON array, but are not valid JSON and will be treated as shell form", keyword);
+                parsed.warnings.push(Diagnostic::warning(line, message));
+            },
+        }
+    }
+
+    parsed.instruction = Some(instruction);
+    parsed
+}
+
+/// Check a single instruction the way the parser checks the text of one, returning every problem
+/// found with it.
+pub(crate) fn check_instruction(line: usize, instruction: &Instruction) -> Vec<Diagnostic> {
+    if let Instruction::Directive(_) = instruction {
+        return vec![];
+    }
+    let parsed = parse_instruction(line, &format!("{} {}", instruction.keyword(), instruction.value()));
+    parsed.errors.into_iter().map(Diagnostic::from).chain(parsed.warnings).collect()
+}
+
+/// Build the instruction corresponding to the given uppercase keyword.
+pub(crate) fn instruction_for(keyword: &str, args: String) -> Option<Instruction> {
+    Some(match keyword {
+        "ADD" => Add::new(args).into(),
+        "ARG" => Arg::new(args).into(),
+        "CMD" => Cmd::new(args).into(),
+        "COPY" => Copy::new(args).into(),
+        "ENTRYPOINT" => Entrypoint::new(args).into(),
+        "ENV" => Env::new(args).into(),
+        "EXPOSE" => Expose::new(args).into(),
+        "FROM" => From::new(args).into(),
+        "HEALTHCHECK" => Healthcheck::new(args).into(),
+        "LABEL" => Label::new(args).into(),
+        "ONBUILD" => Onbuild::new(args).into(),
+        "RUN" => Run::new(args).into(),
+        "SHELL" => Shell::new(args).into(),
+        "STOPSIGNAL" => Stopsignal::new(args).into(),
+        "USER" => User::new(args).into(),
+        "VOLUME" => Volume::new(args).into(),
+        "WORKDIR" => Workdir::new(args).into(),
+        _ => return None,
+    })
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Logical Lines /////////////////////////////////////////////////////////////////////////////////
+
+/// A single logical line of a Dockerfile, with any line continuations joined.
+struct Logical {
+    /// The 1-based line number on which this logical line begins.
+    line: usize,
+    /// The joined text of this logical line.
+    text: String,
+    /// Whether this line is a parser directive, in which case `text` holds `key=value`.
+    directive: bool,
+}
+
+impl Logical {
+    /// Parse this logical line into an instruction, along with any errors found.
+    fn parse(self) -> Parsed {
+        if self.directive {
+            let errors = match self.text.strip_prefix("escape=") {
+                Some(escape) if !is_valid_escape(escape) => vec![ParseError::new(self.line, ParseErrorKind::InvalidEscape(escape.to_string()))],
+                _ => vec![],
+            };
+            return Parsed{instruction: Some(Directive::new(self.text).into()), errors, ..Parsed::default()};
+        }
+        parse_instruction(self.line, &self.text)
+    }
+}
+
+/// Whether the given value of an `escape` directive is one Docker accepts.
+pub(crate) fn is_valid_escape(escape: &str) -> bool {
+    escape == "\\" || escape == "`"
+}
+
+/// An iterator over the logical lines of a Dockerfile.
+///
+/// Blank lines and comments are skipped, parser directives are recognized at the top of the file
+/// and lines ending in the escape character are joined with the line which follows. Once an I/O
+/// error has been yielded, the iterator is exhausted.
+struct LogicalLines<I> {
+    lines: I,
+    line: usize,
+    escape: char,
+    in_directives: bool,
+    done: bool,
+}
+
+impl<I, S> LogicalLines<I>
+    where I: Iterator<Item=io::Result<S>>, S: AsRef<str>,
+{
+    fn new(lines: I) -> Self {
+        LogicalLines{lines, line: 0, escape: '\\', in_directives: true, done: false}
+    }
+
+    /// Attempt to parse the given line as a parser directive, returning `key=value` on success.
+    fn directive(&mut self, line: &str) -> Option<String> {
+        let body = line.trim().strip_prefix('#')?;
+        let (key, value) = body.split_at(body.find('=')?);
+        let (key, value) = (key.trim().to_ascii_lowercase(), value[1..].trim());
+        if !KNOWN_DIRECTIVES.contains(&key.as_str()) || value.is_empty() {
+            return None;
+        }
+        if key == "escape" && is_valid_escape(value) {
+            self.escape = value.chars().next().expect("a valid escape is a single character");
+        }
+        Some(format!("{}={}", key, value))
+    }
+}
+
+impl<I, S> Iterator for LogicalLines<I>
+    where I: Iterator<Item=io::Result<S>>, S: AsRef<str>,
+{
+    type Item = Result<Logical, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut pending: Option<Logical> = None;
+        while let Some(line) = self.lines.next() {
+            self.line += 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    self.done = true;
+                    let kind = ParseErrorKind::Io{kind: err.kind(), message: err.to_string()};
+                    return Some(Err(ParseError::new(self.line, kind)));
+                }
+            };
+            let line = line.as_ref().trim_end_matches('\r');
+            if self.in_directives {
+                if let Some(text) = self.directive(line) {
+                    return Some(Ok(Logical{line: self.line, text, directive: true}));
+                }
+                self.in_directives = false;
+            }
+
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let start = self.line;
+            let logical = pending.get_or_insert_with(|| Logical{line: start, text: String::new(), directive: false});
+            let trimmed_end = line.trim_end();
+            match trimmed_end.strip_suffix(self.escape) {
+                Some(continued) => logical.text.push_str(continued),
+                None => {
+                    logical.text.push_str(line);
+                    return pending.map(Ok);
+                }
+            }
+        }
+        pending.map(Ok)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Streaming /////////////////////////////////////////////////////////////////////////////////////
+
+/// A streaming parser which reads a Dockerfile one instruction at a time.
+///
+/// Only the instruction currently being parsed is held in memory, so arbitrarily large
+/// Dockerfiles may be processed with bounded memory, and analysis may stop early by simply
+/// dropping the iterator. Parsing is strict, in the same way as `Dockerfile::parse`: each
+/// instruction containing an error is yielded as that error, after which parsing continues with
+/// the next instruction. An I/O error ends the stream.
+pub struct InstructionReader<R> {
+    lines: LogicalLines<io::Lines<R>>,
+}
+
+impl<R: BufRead> InstructionReader<R> {
+    /// Create a new streaming parser reading from the given reader.
+    pub fn new(reader: R) -> Self {
+        InstructionReader{lines: LogicalLines::new(reader.lines())}
+    }
+}
+
+impl<R: BufRead> Iterator for InstructionReader<R> {
+    type Item = Result<Instruction, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let parsed = match self.lines.next()? {
+                Ok(logical) => logical.parse(),
+                Err(err) => return Some(Err(err)),
+            };
+            if let Some(err) = parsed.errors.into_iter().next() {
+                return Some(Err(err));
+            }
+            if let Some(instruction) = parsed.instruction {
+                return Some(Ok(instruction));
+            }
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_round_trips_generated_output() {
+        let dockerfile = Dockerfile::base("rust:${RUST_VERSION}-slim")
+            .push_initial_directive(Directive::new("escape=`"))
+            .push_initial_arg(Arg::new("RUST_VERSION=1.31"))
+            .push(Copy::new("--from=builder /out /app"))
+            .push(Cmd::new(r#"["echo", "Hello. Goodbye."]"#))
+            .finish();
+
+        assert_eq!(Dockerfile::parse(&dockerfile.to_string()), Ok(dockerfile));
+    }
+
+    #[test]
+    fn parse_joins_continuations_and_skips_comments() {
+        let dockerfile = Dockerfile::parse(
+r##"FROM alpine
+
+# Install things.
+run apk add \
+    # A comment inside of a continuation.
+    curl \
+
+    git
+"##).unwrap();
+        assert_eq!(dockerfile.to_string(),
+r##"FROM alpine
+RUN apk add     curl     git
+"##)
+    }
+
+    #[test]
+    fn parse_respects_escape_directive() {
+        let dockerfile = Dockerfile::parse("# escape=`\nFROM windows\nRUN dir `\n  c:\\\n").unwrap();
+        assert_eq!(dockerfile.to_string(), "# escape=`\nFROM windows\nRUN dir   c:\\\n")
+    }
+
+    #[test]
+    fn parse_rejects_invalid_escape_tokens() {
+        let input = "# escape=ab\nFROM alpine\nRUN echo a \\\n  b\n";
+        let err = Dockerfile::parse(input).unwrap_err();
+        assert_eq!(err.to_string(), "line 1: invalid escape token 'ab' does not match ` or \\");
+
+        let (dockerfile, diagnostics) = Dockerfile::parse_tolerant(input);
+        assert_eq!(diagnostics, vec![Diagnostic::error(1, "invalid escape token 'ab' does not match ` or \\")]);
+        assert_eq!(dockerfile.instructions()[2], Instruction::Run(Run::new("echo a   b")));
+        assert_eq!(crate::render::escape_char(&dockerfile), '\\');
+    }
+
+    #[test]
+    fn parse_is_strict_about_errors() {
+        let err = Dockerfile::parse("FROM alpine\nRUN echo\nFRM alpine\n").unwrap_err();
+        assert_eq!(err.line(), 3);
+        assert_eq!(err.kind(), &ParseErrorKind::UnknownInstruction("FRM".into()));
+
+        let err = Dockerfile::parse("FROM alpine\nCMD [\"echo\", 1]\n").unwrap_err();
+        assert_eq!(err.kind(), &ParseErrorKind::NotStringArray);
+
+        let err = Dockerfile::parse("FROM alpine\nSHELL /bin/sh -c\n").unwrap_err();
+        assert_eq!(err.kind(), &ParseErrorKind::JsonRequired("SHELL".into()));
+
+        let err = Dockerfile::parse("FROM alpine\nCOPY --=builder /out /app\n").unwrap_err();
+        assert_eq!(err.kind(), &ParseErrorKind::Flag(FlagError::Malformed("--=builder".into())));
+
+        let err = Dockerfile::parse("FROM alpine\nCOPY --form=builder /out /app\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown flag: form");
+    }
+
+    #[test]
+    fn parse_tolerant_recovers_from_errors() {
+        let (dockerfile, diagnostics) = Dockerfile::parse_tolerant(
+r##"FROM alpine
+FRM alpine
+COPY --=builder /out /app
+CMD ["echo",
+RUN
+"##);
+        assert_eq!(dockerfile.to_string(),
+            "FROM alpine\nCOPY --=builder /out /app\nCMD [\"echo\",\nRUN \n");
+        assert_eq!(diagnostics, vec![
+            Diagnostic::error(2, "unknown instruction: FRM"),
+            Diagnostic::error(3, "malformed flag: --=builder"),
+            Diagnostic::warning(4, "CMD arguments look like a JSON array, but are not valid JSON and will be treated as shell form"),
+            Diagnostic::error(5, "RUN requires at least one argument"),
+        ]);
+    }
+
+    #[test]
+    fn instruction_reader_streams_instructions() {
+        let input = "# syntax=docker/dockerfile:1\r\nFROM alpine\r\nRUN apk add \\\r\n  curl\r\nFRM alpine\r\nCMD sh\r\n";
+        let mut reader = InstructionReader::new(input.as_bytes());
+        assert_eq!(reader.next(), Some(Ok(Directive::new("syntax=docker/dockerfile:1").into())));
+        assert_eq!(reader.next(), Some(Ok(From::new("alpine").into())));
+        assert_eq!(reader.next(), Some(Ok(Run::new("apk add   curl").into())));
+        assert_eq!(reader.next().unwrap().unwrap_err().line(), 5);
+        assert_eq!(reader.next(), Some(Ok(Cmd::new("sh").into())));
+        assert_eq!(reader.next(), None);
+    }
+
+    #[test]
+    fn instruction_reader_stops_on_io_errors() {
+        struct Failing;
+        impl io::Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk on fire"))
+            }
+        }
+
+        let mut reader = InstructionReader::new(io::BufReader::new(Failing));
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "line 1: I/O error: disk on fire");
+        assert_eq!(reader.next(), None);
+    }
+
+    #[test]
+    fn instruction_types_parse_from_single_lines() {
+        let copy: Copy = "COPY --from=builder /out /app".parse().unwrap();
+        assert_eq!(copy, Copy::new("--from=builder /out /app"));
+
+        let directive: Directive = "# escape=`".parse().unwrap();
+        assert_eq!(directive, Directive::new("escape=`"));
+
+        let instruction: Instruction = "healthcheck CMD pgrep 1".parse().unwrap();
+        assert_eq!(instruction, Healthcheck::new("CMD pgrep 1").into());
+    }
+
+    #[test]
+    fn instruction_types_reject_other_input() {
+        let err = "RUN echo".parse::<Copy>().unwrap_err();
+        assert_eq!(err.kind(), &ParseErrorKind::UnexpectedInstruction{expected: ExpectedInstruction::Keyword("COPY"), found: Some("RUN")});
+
+        let err = "".parse::<Copy>().unwrap_err();
+        assert_eq!(err.to_string(), "line 1: expected COPY instruction, found nothing");
+        assert_eq!("".parse::<Instruction>().unwrap_err().to_string(), "line 1: expected an instruction, found nothing");
+        assert_eq!("".parse::<Directive>().unwrap_err().to_string(), "line 1: expected a parser directive, found nothing");
+        assert_eq!("RUN a".parse::<Directive>().unwrap_err().to_string(), "line 1: expected a parser directive, found RUN");
+        assert_eq!("# escape=`".parse::<Run>().unwrap_err().to_string(), "line 1: expected RUN instruction, found a parser directive");
+
+        let err = "COPY a b\nCOPY c d".parse::<Copy>().unwrap_err();
+        assert_eq!((err.line(), err.kind()), (2, &ParseErrorKind::TrailingInput));
+
+        let err = "COPY".parse::<Copy>().unwrap_err();
+        assert_eq!(err.kind(), &ParseErrorKind::MissingArguments("COPY".into()));
+    }
+
+    #[test]
+    fn parse_follows_docker_json_fallback_rules() {
+        let (dockerfile, diagnostics) = Dockerfile::parse_tolerant(
+r##"FROM alpine
+COPY --chown=app ["a b", "/app/"]
+VOLUME /data /cache
+RUN ["echo", 'hi']
+CMD [ "echo", "hi" ]
+"##);
+        let forms: Vec<_> = dockerfile.instructions().iter().map(Instruction::form).collect();
+        assert_eq!(forms, vec![
+            None,
+            Some(Form::Json(vec!["a b".into(), "/app/".into()])),
+            Some(Form::Shell("/data /cache")),
+            Some(Form::Shell(r#"["echo", 'hi']"#)),
+            Some(Form::Json(vec!["echo".into(), "hi".into()])),
+        ]);
+        assert_eq!(diagnostics, vec![
+            Diagnostic::warning(4, "RUN arguments look like a JSON array, but are not valid JSON and will be treated as shell form"),
+        ]);
+    }
+}
@@ -0,0 +1,723 @@
+use std::{
+    borrow::Cow,
+    fmt::{
+        self,
+        Write,
+    },
+    io,
+};
+
+use crate::{
+    builder::{
+        Dockerfile,
+        Provenance,
+    },
+    flags::Flags,
+    form::Form,
+    instructions::Instruction,
+    parser::is_valid_escape,
+};
+
+/// The case in which instruction keywords are rendered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeywordCase {
+    /// `RUN`, `COPY` &c. This is the conventional style.
+    Upper,
+    /// `run`, `copy` &c.
+    Lower,
+}
+
+/// The line endings used when rendering a Dockerfile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, as used on Unix-like systems.
+    Lf,
+    /// `\r\n`, as used on Windows.
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Options controlling how a Dockerfile is rendered by `Dockerfile::render`.
+///
+/// The default options produce exactly the same output as the `Display` impl of `Dockerfile`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// The case in which instruction keywords are rendered.
+    pub keyword_case: KeywordCase,
+
+    /// Whether the keyword of the instruction nested within an `ONBUILD`, such as the `run` of
+    /// `ONBUILD run make`, is rendered in `keyword_case` as well. Otherwise it is left as written.
+    pub case_nested_keywords: bool,
+
+    /// The maximum width of a line, in characters.
+    ///
+    /// Instructions which would exceed this width are broken onto multiple lines at whitespace
+    /// outside of any quotes, using the escape character for line continuations. Instructions
+    /// in which whitespace is significant, such as `WORKDIR`, are never broken. Shell-form `RUN`
+    /// instructions are first broken at the `&&`, `||`, `;` and `|` operators of their command
+    /// lists, placing one command on each line. Lines may still exceed this width when there is
+    /// nowhere to break them. `None` disables wrapping.
+    pub max_line_width: Option<usize>,
+
+    /// The number of spaces by which continuation lines are indented.
+    pub continuation_indent: usize,
+
+    /// The number of blank lines inserted before each `FROM` instruction after the first.
+    pub blank_lines_between_stages: usize,
+
+    /// The line endings to use.
+    pub line_ending: LineEnding,
+
+    /// Whether the final line is followed by a line ending.
+    pub trailing_newline: bool,
+
+    /// Whether each instruction is preceded by a comment naming the location in Rust source code
+    /// at which it was added to its builder, such as `# from src/gen/rust.rs:42`.
+    ///
+    /// This is intended for debugging generated Dockerfiles. Instructions with no known
+    /// provenance, as well as parser directives, get no comment.
+    pub provenance_comments: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions{
+            keyword_case: KeywordCase::Upper,
+            case_nested_keywords: false,
+            max_line_width: None,
+            continuation_indent: 4,
+            blank_lines_between_stages: 0,
+            line_ending: LineEnding::Lf,
+            trailing_newline: true,
+            provenance_comments: false,
+        }
+    }
+}
+
+impl Dockerfile {
+    /// Render this Dockerfile as a string, according to the given options.
+    pub fn render(&self, options: &RenderOptions) -> String {
+        let mut out = String::new();
+        Renderer::new(self, options).write_to(&mut out).expect("writing to a String never fails");
+        out
+    }
+
+    /// Render this Dockerfile as a string, along with a source map relating each rendered
+    /// instruction to the location in Rust source code at which it was added to its builder.
+    ///
+    /// The source map is intended to be written alongside the Dockerfile as a sidecar file.
+    pub fn render_with_source_map(&self, options: &RenderOptions) -> (String, SourceMap) {
+        let mut out = LineCounter{inner: String::new(), lines: 0};
+        let mut mappings = vec![];
+        Renderer::new(self, options).write_tracked(&mut out, Some(&mut mappings)).expect("writing to a String never fails");
+        (out.inner, SourceMap(mappings))
+    }
+}
+
+/// A map from the lines of a rendered Dockerfile to the Rust source code which produced them.
+///
+/// Its `Display` impl renders one mapping per line, as `<line> <file>:<line>:<column>`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap(Vec<SourceMapping>);
+
+/// A single entry of a `SourceMap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceMapping {
+    /// The 1-based line of the rendered Dockerfile on which the instruction begins.
+    pub line: usize,
+
+    /// The location at which the instruction was added to its builder.
+    pub provenance: Provenance,
+}
+
+impl SourceMap {
+    /// All of the mappings, ordered by line.
+    pub fn mappings(&self) -> &[SourceMapping] {
+        &self.0
+    }
+
+    /// Find the provenance of the instruction rendered on the given 1-based line, which may be a
+    /// continuation line of a wrapped instruction.
+    pub fn lookup(&self, line: usize) -> Option<Provenance> {
+        let idx = self.0.partition_point(|mapping| mapping.line <= line);
+        idx.checked_sub(1).map(|idx| self.0[idx].provenance)
+    }
+}
+
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for mapping in &self.0 {
+            writeln!(f, "{} {}", mapping.line, mapping.provenance)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `fmt::Write` which counts the lines written through it.
+struct LineCounter<W> {
+    inner: W,
+    lines: usize,
+}
+
+impl<W: Write> Write for LineCounter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.lines += s.bytes().filter(|&b| b == b'\n').count();
+        self.inner.write_str(s)
+    }
+}
+
+impl Dockerfile {
+    /// Stream this Dockerfile to the given writer, exactly as it is rendered by `Display`.
+    ///
+    /// Instructions are written one at a time without building up the whole Dockerfile in memory
+    /// first. Wrap unbuffered writers such as files in an `io::BufWriter`.
+    pub fn write_to<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut adapter = IoAdapter{inner: out, error: None};
+        match Renderer::new(self, &RenderOptions::default()).write_to(&mut adapter) {
+            Ok(()) => Ok(()),
+            Err(fmt::Error) => Err(adapter.error.unwrap_or_else(|| io::Error::other("formatter error"))),
+        }
+    }
+
+    /// Stream this Dockerfile to the given `fmt::Write`, exactly as it is rendered by `Display`.
+    pub fn write_to_fmt<W: Write>(&self, out: &mut W) -> fmt::Result {
+        Renderer::new(self, &RenderOptions::default()).write_to(out)
+    }
+}
+
+/// Adapts an `io::Write` into a `fmt::Write`, holding on to the first I/O error encountered.
+struct IoAdapter<'a, W> {
+    inner: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: io::Write> Write for IoAdapter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+/// Renders the instructions of a Dockerfile to any `fmt::Write`.
+pub(crate) struct Renderer<'a> {
+    dockerfile: &'a Dockerfile,
+    options: &'a RenderOptions,
+    escape: char,
+}
+
+impl<'a> Renderer<'a> {
+    pub(crate) fn new(dockerfile: &'a Dockerfile, options: &'a RenderOptions) -> Self {
+        Renderer{dockerfile, options, escape: escape_char(dockerfile)}
+    }
+
+    pub(crate) fn write_to<W: Write>(&self, out: &mut W) -> fmt::Result {
+        let mut out = LineCounter{inner: out, lines: 0};
+        self.write_tracked(&mut out, None)
+    }
+
+    /// Write the Dockerfile, recording the line on which each instruction with a known
+    /// provenance begins into `mappings`, if given.
+    fn write_tracked<W: Write>(&self, out: &mut LineCounter<W>, mut mappings: Option<&mut Vec<SourceMapping>>) -> fmt::Result {
+        let ending = self.options.line_ending.as_str();
+        let mut seen_from = false;
+        for (idx, instruction) in self.dockerfile.instructions().iter().enumerate() {
+            if idx > 0 {
+                out.write_str(ending)?;
+            }
+            if let Instruction::From(_) = instruction {
+                if seen_from {
+                    for _ in 0..self.options.blank_lines_between_stages {
+                        out.write_str(ending)?;
+                    }
+                }
+                seen_from = true;
+            }
+            if let (Some(provenance), false) = (self.dockerfile.provenance(idx), matches!(instruction, Instruction::Directive(_))) {
+                if self.options.provenance_comments {
+                    write!(out, "# from {}:{}{}", provenance.file(), provenance.line(), ending)?;
+                }
+                if let Some(mappings) = mappings.as_mut() {
+                    mappings.push(SourceMapping{line: out.lines + 1, provenance});
+                }
+            }
+            self.write_instruction(instruction, out)?;
+        }
+        if self.options.trailing_newline && !self.dockerfile.instructions().is_empty() {
+            out.write_str(ending)?;
+        }
+        Ok(())
+    }
+
+    /// Write a single instruction, without its final line ending.
+    ///
+    /// Unless the instruction needs to be wrapped or have its case changed, this writes straight
+    /// from the instruction without allocating.
+    fn write_instruction<W: Write>(&self, instruction: &Instruction, out: &mut W) -> fmt::Result {
+        let value = instruction.value();
+        if let Instruction::Directive(_) = instruction {
+            out.write_str("# ")?;
+            return out.write_str(value);
+        }
+        let keyword = self.keyword(instruction.keyword());
+
+        // The instruction nested within an `ONBUILD` has its keyword cased to match.
+        let value = match instruction {
+            Instruction::Onbuild(_) if self.options.case_nested_keywords => {
+                let (nested, rest) = value.split_at(value.find(char::is_whitespace).unwrap_or(value.len()));
+                match self.keyword(nested) {
+                    Cow::Borrowed(_) => Cow::Borrowed(value),
+                    Cow::Owned(nested) => Cow::Owned(nested + rest),
+                }
+            }
+            _ => Cow::Borrowed(value),
+        };
+
+        let too_long = |width| keyword.len() + 1 + value.chars().count() > width;
+        match self.options.max_line_width {
+            Some(width) if is_wrappable(instruction) && too_long(width) => {
+                let prefix = format!("{} ", keyword);
+                if let Instruction::Run(_) = instruction {
+                    if let Some(result) = self.write_run(&prefix, &value, width, out) {
+                        return result;
+                    }
+                }
+                out.write_str(&prefix)?;
+                let indent = " ".repeat(self.options.continuation_indent);
+                self.write_words(prefix.chars().count(), &value, width, &indent, out)
+            }
+            _ => {
+                out.write_str(&keyword)?;
+                out.write_char(' ')?;
+                out.write_str(&value)
+            }
+        }
+    }
+
+    /// Write a shell-form `RUN` instruction with one command of its `&&`, `||`, `;` or `|` list
+    /// per line, each command being wrapped further as needed.
+    ///
+    /// Returns `None`, without writing anything, if the command is not a list of commands.
+    fn write_run<W: Write>(&self, prefix: &str, value: &str, width: usize, out: &mut W) -> Option<fmt::Result> {
+        let script = Flags::parse(value).map(|(_, rest)| rest).unwrap_or(value);
+        if let Some(Form::Json(_)) = Form::detect(script) {
+            return None;
+        }
+        let commands = split_commands(script);
+        if commands.len() < 2 {
+            return None;
+        }
+
+        let flags = value[..value.len() - script.len()].trim_end();
+        let indent = " ".repeat(self.options.continuation_indent);
+        let mut write = || -> fmt::Result {
+            out.write_str(prefix)?;
+            let mut start = prefix.chars().count();
+            if !flags.is_empty() {
+                write!(out, "{} ", flags)?;
+                start += flags.chars().count() + 1;
+            }
+            for (idx, (op, command)) in commands.iter().enumerate() {
+                if idx > 0 {
+                    // A line starting with `#` would be dropped as a comment, so such a command
+                    // stays on the line of the one before it.
+                    match *op {
+                        ";" if command.starts_with('#') => {
+                            out.write_str("; ")?;
+                            start += 2;
+                        }
+                        ";" => {
+                            write!(out, ";{}{}", self.continuation(), indent)?;
+                            start = indent.len();
+                        }
+                        op => {
+                            write!(out, "{}{}{} ", self.continuation(), indent, op)?;
+                            start = indent.len() + op.len() + 1;
+                        }
+                    }
+                }
+                // Continuation lines of a single command are aligned with the command itself,
+                // apart from the first command, which may follow arbitrarily long flags.
+                let aligned = " ".repeat(if idx == 0 { indent.len() } else { start });
+                self.write_words(start, command, width, &aligned, out)?;
+            }
+            Ok(())
+        };
+        Some(write())
+    }
+
+    /// Write the given text, breaking it at whitespace outside of quotes to fit within `width`.
+    ///
+    /// `start` is the width of whatever has already been written on the current line, and every
+    /// continuation line begins with `indent`. The text is never broken before a word starting
+    /// with `#`, as Docker would take the continuation line for a comment and drop it.
+    fn write_words<W: Write>(&self, start: usize, text: &str, width: usize, indent: &str, out: &mut W) -> fmt::Result {
+        let mut line_width = start;
+        for (idx, (space, word)) in split_words(text, self.escape).into_iter().enumerate() {
+            let word_width = word.chars().count();
+            // Leave room for the trailing ` \` of a continuation.
+            if idx > 0 && !word.starts_with('#') && line_width + space.chars().count() + word_width + 2 > width {
+                out.write_str(&self.continuation())?;
+                out.write_str(indent)?;
+                line_width = indent.len();
+            } else {
+                out.write_str(space)?;
+                line_width += space.chars().count();
+            }
+            out.write_str(word)?;
+            line_width += word_width;
+        }
+        Ok(())
+    }
+
+    /// The text which ends a line which is continued onto the next.
+    fn continuation(&self) -> String {
+        format!(" {}{}", self.escape, self.options.line_ending.as_str())
+    }
+
+    /// Convert a keyword to the configured case, borrowing it if it is already in that case.
+    fn keyword<'k>(&self, keyword: &'k str) -> Cow<'k, str> {
+        match self.options.keyword_case {
+            KeywordCase::Upper if keyword.bytes().any(|b| b.is_ascii_lowercase()) => Cow::Owned(keyword.to_ascii_uppercase()),
+            KeywordCase::Lower if keyword.bytes().any(|b| b.is_ascii_uppercase()) => Cow::Owned(keyword.to_ascii_lowercase()),
+            _ => Cow::Borrowed(keyword),
+        }
+    }
+}
+
+/// Find the escape character declared by the Dockerfile's `escape` directive, if any.
+pub(crate) fn escape_char(dockerfile: &Dockerfile) -> char {
+    escape_char_of(dockerfile.instructions())
+}
+
+/// Find the escape character declared by the `escape` directive at the start of the given
+/// instructions, if any.
+pub(crate) fn escape_char_of(instructions: &[Instruction]) -> char {
+    instructions.iter()
+        .take_while(|inst| matches!(inst, Instruction::Directive(_)))
+        .filter_map(|inst| inst.value().strip_prefix("escape="))
+        .map(str::trim)
+        .filter(|escape| is_valid_escape(escape))
+        .filter_map(|escape| escape.chars().next())
+        .last()
+        .unwrap_or('\\')
+}
+
+/// Whether an instruction may be broken at whitespace without changing its meaning.
+fn is_wrappable(instruction: &Instruction) -> bool {
+    if instruction.value().contains('\n') {
+        return false;
+    }
+    match instruction {
+        Instruction::Add(_) | Instruction::Cmd(_) | Instruction::Copy(_) | Instruction::Entrypoint(_)
+            | Instruction::Expose(_) | Instruction::From(_) | Instruction::Healthcheck(_) | Instruction::Label(_)
+            | Instruction::Onbuild(_) | Instruction::Run(_) | Instruction::Shell(_) | Instruction::Volume(_) => true,
+        // The legacy `ENV key value` form takes the rest of the line verbatim.
+        Instruction::Env(_) => instruction.value().split_whitespace().next().is_some_and(|word| word.contains('=')),
+        _ => false,
+    }
+}
+
+/// Split a shell script into the commands of its top-level `&&`, `||`, `;` and `|` list.
+///
+/// Each command is returned along with the operator which preceded it, the first command having
+/// an empty operator. Operators within quotes, escaped with a backslash or nested within
+/// parentheses are not split upon, and neither is the `;;` of a `case` statement.
+pub(crate) fn split_commands(script: &str) -> Vec<(&str, &str)> {
+    let bytes = script.as_bytes();
+    let mut commands = vec![];
+    let (mut quote, mut depth, mut escaped) = (None, 0usize, false);
+    let (mut op, mut start, mut idx) = ("", 0, 0);
+    while idx < bytes.len() {
+        let c = bytes[idx];
+        let next = bytes.get(idx + 1).copied();
+        let found = match (quote, c) {
+            _ if escaped => {
+                escaped = false;
+                None
+            }
+            (Some(b'\''), b'\'') => {
+                quote = None;
+                None
+            }
+            (Some(b'\''), _) => None,
+            (_, b'\\') => {
+                escaped = true;
+                None
+            }
+            (Some(q), c) if q == c => {
+                quote = None;
+                None
+            }
+            (Some(_), _) => None,
+            (None, b'"') | (None, b'\'') | (None, b'`') => {
+                quote = Some(c);
+                None
+            }
+            (None, b'(') => {
+                depth += 1;
+                None
+            }
+            (None, b')') => {
+                depth = depth.saturating_sub(1);
+                None
+            }
+            (None, _) if depth > 0 => None,
+            (None, b'&') if next == Some(b'&') => Some("&&"),
+            (None, b'|') if next == Some(b'|') => Some("||"),
+            (None, b'|') if idx > 0 && bytes[idx - 1] == b'>' => None,
+            (None, b'|') if next == Some(b'&') => None,
+            (None, b'|') => Some("|"),
+            (None, b';') if next == Some(b';') => {
+                idx += 1;
+                None
+            }
+            (None, b';') => Some(";"),
+            _ => None,
+        };
+        match found {
+            Some(found) => {
+                commands.push((op, script[start..idx].trim()));
+                op = found;
+                idx += found.len();
+                start = idx;
+            }
+            None => idx += 1,
+        }
+    }
+    commands.push((op, script[start..].trim()));
+    commands.retain(|(_, command)| !command.is_empty());
+    commands
+}
+
+/// Split text into words at whitespace outside of quotes.
+///
+/// The given escape character escapes the character which follows it, outside of single quotes.
+/// Each word is returned along with the whitespace which preceded it.
+pub(crate) fn split_words(text: &str, escape: char) -> Vec<(&str, &str)> {
+    let mut words = vec![];
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let (mut space_start, mut word_start) = (0, None);
+    for (idx, c) in text.char_indices() {
+        if let Some(start) = word_start {
+            let breaks = !escaped && quote.is_none() && c.is_whitespace();
+            if breaks {
+                words.push((&text[space_start..start], &text[start..idx]));
+                word_start = None;
+                space_start = idx;
+                continue;
+            }
+        } else if c.is_whitespace() {
+            continue;
+        } else {
+            word_start = Some(idx);
+        }
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => (),
+            (_, c) if c == escape => escaped = true,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            _ => (),
+        }
+    }
+    if let Some(start) = word_start {
+        words.push((&text[space_start..start], &text[start..]));
+    }
+    words
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instructions::{
+        Arg,
+        Copy,
+        Directive,
+        Env,
+        From,
+        Label,
+        Onbuild,
+        Run,
+        Workdir,
+    };
+
+    fn multi_stage() -> Dockerfile {
+        Dockerfile::base("rust:1.31 AS builder")
+            .push(Run::new("cargo build --release"))
+            .push(From::new("debian:stretch-slim"))
+            .push(Copy::new("--from=builder /app/target/release/app /usr/local/bin/app"))
+            .push(Onbuild::new("RUN echo 'hi'"))
+            .finish()
+    }
+
+    #[test]
+    fn render_with_default_options_matches_display() {
+        let dockerfile = multi_stage();
+        assert_eq!(dockerfile.render(&RenderOptions::default()), dockerfile.to_string());
+    }
+
+    #[test]
+    fn display_keeps_keyword_nested_in_onbuild() {
+        let dockerfile = Dockerfile::base("alpine").push(Onbuild::new("run make")).finish();
+        assert_eq!(dockerfile.to_string(), "FROM alpine\nONBUILD run make\n");
+        assert_eq!(dockerfile.render(&RenderOptions::default()), dockerfile.to_string());
+        let options = RenderOptions{case_nested_keywords: true, ..RenderOptions::default()};
+        assert_eq!(dockerfile.render(&options), "FROM alpine\nONBUILD RUN make\n");
+    }
+
+    #[test]
+    fn write_to_streams_display_output() {
+        let dockerfile = multi_stage();
+        let mut bytes = vec![];
+        dockerfile.write_to(&mut bytes).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), dockerfile.to_string());
+
+        let mut text = String::new();
+        dockerfile.write_to_fmt(&mut text).unwrap();
+        assert_eq!(text, dockerfile.to_string());
+    }
+
+    #[test]
+    fn write_to_reports_io_errors() {
+        let mut full = [0u8; 8];
+        let err = multi_stage().write_to(&mut &mut full[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn render_with_custom_options() {
+        let options = RenderOptions{
+            keyword_case: KeywordCase::Lower,
+            case_nested_keywords: true,
+            blank_lines_between_stages: 1,
+            line_ending: LineEnding::CrLf,
+            trailing_newline: false,
+            ..RenderOptions::default()
+        };
+        assert_eq!(multi_stage().render(&options),
+            "from rust:1.31 AS builder\r\nrun cargo build --release\r\n\r\nfrom debian:stretch-slim\r\n\
+             copy --from=builder /app/target/release/app /usr/local/bin/app\r\nonbuild run echo 'hi'");
+    }
+
+    #[test]
+    fn render_wraps_long_lines_outside_of_quotes() {
+        let dockerfile = Dockerfile::base("alpine")
+            .push_initial_arg(Arg::new("A_RATHER_LONG_ARGUMENT_NAME=with-a-long-default-value"))
+            .push(Env::new(r#"GREETING="hello there, world" PATH=/usr/local/bin:$PATH"#))
+            .push(Workdir::new("/a directory/with a rather long/path in it"))
+            .finish();
+        let options = RenderOptions{max_line_width: Some(30), continuation_indent: 2, ..RenderOptions::default()};
+        assert_eq!(dockerfile.render(&options),
+r##"ARG A_RATHER_LONG_ARGUMENT_NAME=with-a-long-default-value
+FROM alpine
+ENV GREETING="hello there, world" \
+  PATH=/usr/local/bin:$PATH
+WORKDIR /a directory/with a rather long/path in it
+"##);
+        assert_eq!(Dockerfile::parse(&dockerfile.render(&options)).unwrap().instructions()[2],
+            Env::new(r#"GREETING="hello there, world"   PATH=/usr/local/bin:$PATH"#).into());
+    }
+
+    #[test]
+    fn render_does_not_wrap_before_comment_characters() {
+        let dockerfile = Dockerfile::base("alpine")
+            .push(Run::new("echo the colour of the day is #ff0000 #00ff00"))
+            .finish();
+        let options = RenderOptions{max_line_width: Some(30), continuation_indent: 2, ..RenderOptions::default()};
+        let rendered = dockerfile.render(&options);
+        assert_eq!(rendered, "FROM alpine\nRUN echo the colour of the \\\n  day is #ff0000 #00ff00\n");
+        let dockerfile = Dockerfile::base("alpine")
+            .push(Label::new("description=\"a long description\" # \"#tag\""))
+            .finish();
+        let rendered = dockerfile.render(&options);
+        assert_eq!(rendered, "FROM alpine\nLABEL description=\"a long description\" # \\\n  \"#tag\"\n");
+        assert_eq!(Dockerfile::parse(&rendered).unwrap().instructions()[1].value().split_whitespace().collect::<Vec<_>>(),
+            vec!["description=\"a", "long", "description\"", "#", "\"#tag\""]);
+    }
+
+    #[test]
+    fn render_wraps_run_at_command_boundaries() {
+        let dockerfile = Dockerfile::base("debian")
+            .push(Run::new("--mount=type=cache,target=/var/cache/apt apt-get update && apt-get install -y --no-install-recommends curl ca-certificates git \
+                || echo 'a && b; c' | tee /log; find / -name '*.tmp' -exec rm {} \\; && (cd /tmp; make)"))
+            .finish();
+        let options = RenderOptions{max_line_width: Some(64), ..RenderOptions::default()};
+        let rendered = dockerfile.render(&options);
+        assert_eq!(rendered,
+r##"FROM debian
+RUN --mount=type=cache,target=/var/cache/apt apt-get update \
+    && apt-get install -y --no-install-recommends curl \
+       ca-certificates git \
+    || echo 'a && b; c' \
+    | tee /log; \
+    find / -name '*.tmp' -exec rm {} \; \
+    && (cd /tmp; make)
+"##);
+
+        fn commands(run: &Instruction) -> Vec<Vec<&str>> {
+            split_commands(run.value()).into_iter().map(|(_, cmd)| cmd.split_whitespace().collect()).collect()
+        }
+        let reparsed = Dockerfile::parse(&rendered).unwrap();
+        assert_eq!(commands(&reparsed.instructions()[1]), commands(&dockerfile.instructions()[1]));
+    }
+
+    #[test]
+    fn render_wraps_run_without_starting_lines_with_comments() {
+        let dockerfile = Dockerfile::base("debian")
+            .push(Run::new("apt-get update; #refresh the lists && apt-get install -y curl; echo done"))
+            .finish();
+        let options = RenderOptions{max_line_width: Some(40), ..RenderOptions::default()};
+        assert_eq!(dockerfile.render(&options), r##"FROM debian
+RUN apt-get update; #refresh the lists \
+    && apt-get install -y curl; \
+    echo done
+"##);
+    }
+
+    #[test]
+    fn render_wraps_run_with_escape_directive() {
+        let dockerfile = Dockerfile::base("windows")
+            .push_initial_directive(Directive::new("escape=`"))
+            .push(Run::new("choco install -y git && choco install -y python"))
+            .finish();
+        let options = RenderOptions{max_line_width: Some(30), continuation_indent: 2, ..RenderOptions::default()};
+        assert_eq!(dockerfile.render(&options), "# escape=`\nFROM windows\nRUN choco install -y git `\n  && choco install -y python\n");
+    }
+
+    #[test]
+    fn render_with_provenance() {
+        let line = line!();
+        let dockerfile = Dockerfile::base("alpine")
+            .push_initial_directive(Directive::new("escape=`"))
+            .push(Run::new("apk add curl && apk add git"))
+            .finish();
+        let dockerfile = crate::fmt::format(&dockerfile);
+        let options = RenderOptions{provenance_comments: true, max_line_width: Some(20), ..RenderOptions::default()};
+        let (rendered, source_map) = dockerfile.render_with_source_map(&options);
+        assert_eq!(rendered, format!(
+            "# escape=`\n# from src/render.rs:{0}\nFROM alpine\n# from src/render.rs:{1}\nRUN apk add curl `\n    && apk add git\n",
+            line + 1, line + 3));
+        assert_eq!(source_map.to_string(), format!("3 src/render.rs:{}:26\n5 src/render.rs:{}:14\n", line + 1, line + 3));
+        assert_eq!(source_map.lookup(6).map(|loc| loc.line()), Some(line + 3));
+        assert_eq!(source_map.lookup(1), None);
+
+        let parsed = Dockerfile::parse(&rendered).unwrap();
+        assert_eq!(parsed.instructions().len(), 3);
+        assert_eq!(parsed.provenance(1), None);
+    }
+}
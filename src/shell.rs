@@ -0,0 +1,1364 @@
+//! A parser for the POSIX shell commands of shell form `RUN`, `CMD` and `ENTRYPOINT`.
+//!
+//! `parse` turns a script into a `Script`, a syntax tree of `&&` and `||` lists, pipelines and
+//! commands, down to the quoting and expansions within each `Word`. Every node keeps the text it
+//! was parsed from, which is a slice of the script, so that positions within an instruction can
+//! be found for diagnostics. Nothing is expanded or executed.
+//!
+//! The parser covers the POSIX shell grammar: simple commands with assignments and redirections,
+//! pipelines, lists, subshells, brace groups, `if`, `while`, `until`, `for` and `case`, and
+//! function definitions. The bodies of here-documents are not part of a single line, so only
+//! their delimiters are parsed.
+
+use std::{
+    error,
+    fmt,
+};
+
+use crate::{
+    builder::Dockerfile,
+    form::Form,
+    instructions::Instruction,
+    stage::{
+        self,
+        EdgeKind,
+    },
+};
+
+/// A sequence of `&&` and `||` lists, separated by `;`, `&` or newlines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Script<'a> {
+    /// The text of the script, from the start of its first list to the end of its last.
+    pub text: &'a str,
+
+    /// The lists of the script, in order.
+    pub lists: Vec<AndOrList<'a>>,
+}
+
+/// Pipelines joined by `&&` and `||`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AndOrList<'a> {
+    /// The text of the list, excluding any terminating `;` or `&`.
+    pub text: &'a str,
+
+    /// The first pipeline of the list.
+    pub first: Pipeline<'a>,
+
+    /// The remaining pipelines, each with the operator which precedes it.
+    pub rest: Vec<(LogicalOp, Pipeline<'a>)>,
+
+    /// Whether the list is terminated by `&`, and so runs in the background.
+    pub background: bool,
+}
+
+/// The operator joining two pipelines of an `AndOrList`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LogicalOp {
+    /// `&&`, which runs the following pipeline only if the preceding one succeeds.
+    And,
+    /// `||`, which runs the following pipeline only if the preceding one fails.
+    Or,
+}
+
+/// Commands joined by `|`, each reading the output of the one before.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pipeline<'a> {
+    /// The text of the pipeline.
+    pub text: &'a str,
+
+    /// Whether the pipeline is preceded by `!`, which negates its exit status.
+    pub negated: bool,
+
+    /// The commands of the pipeline, in order.
+    pub commands: Vec<Command<'a>>,
+}
+
+/// A single command of a pipeline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Command<'a> {
+    /// The text of the command, including its redirections.
+    pub text: &'a str,
+
+    /// The kind of command.
+    pub kind: CommandKind<'a>,
+
+    /// The redirections of the command, in order.
+    pub redirects: Vec<Redirect<'a>>,
+}
+
+/// The different kinds of command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandKind<'a> {
+    /// A simple command, such as `DEBIAN_FRONTEND=noninteractive apt-get update`.
+    Simple {
+        /// The variable assignments preceding the command.
+        assignments: Vec<Assignment<'a>>,
+        /// The words of the command, beginning with its name. This is empty for a command made
+        /// up only of assignments or redirections.
+        words: Vec<Word<'a>>,
+    },
+
+    /// A script run in a subshell, as in `( cd /tmp && make )`.
+    Subshell(Script<'a>),
+
+    /// A script run in the current shell, as in `{ make; make install; }`.
+    Group(Script<'a>),
+
+    /// An `if` command.
+    If {
+        /// The condition and body of the `if` and of every `elif`, in order.
+        branches: Vec<(Script<'a>, Script<'a>)>,
+        /// The body of the `else`.
+        otherwise: Option<Script<'a>>,
+    },
+
+    /// A `while` or `until` loop.
+    While {
+        /// Whether this is an `until` loop, which runs while its condition fails.
+        until: bool,
+        condition: Script<'a>,
+        body: Script<'a>,
+    },
+
+    /// A `for` loop.
+    For {
+        /// The name of the loop variable.
+        variable: &'a str,
+        /// The words following `in`, or `None` to loop over the positional parameters.
+        items: Option<Vec<Word<'a>>>,
+        body: Script<'a>,
+    },
+
+    /// A `case` command.
+    Case {
+        /// The word matched against the patterns.
+        word: Word<'a>,
+        arms: Vec<CaseArm<'a>>,
+    },
+
+    /// The definition of a function, as in `build() { make; }`.
+    Function {
+        name: &'a str,
+        body: Box<Command<'a>>,
+    },
+}
+
+/// A single arm of a `case` command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaseArm<'a> {
+    /// The patterns which select this arm.
+    pub patterns: Vec<Word<'a>>,
+
+    /// The script run when a pattern matches.
+    pub body: Script<'a>,
+}
+
+/// A variable assignment preceding a simple command, such as `PATH=/usr/bin`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assignment<'a> {
+    /// The text of the assignment.
+    pub text: &'a str,
+
+    /// The name of the variable.
+    pub name: &'a str,
+
+    /// The value assigned, which may be empty.
+    pub value: Word<'a>,
+}
+
+/// A redirection, such as `2>&1` or `> /dev/null`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Redirect<'a> {
+    /// The text of the redirection.
+    pub text: &'a str,
+
+    /// The file descriptor given before the operator, if any.
+    pub fd: Option<u32>,
+
+    pub operator: RedirectOp,
+
+    /// The file, file descriptor or here-document delimiter following the operator.
+    pub target: Word<'a>,
+}
+
+/// The operator of a redirection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RedirectOp {
+    /// `<`
+    Input,
+    /// `>`
+    Output,
+    /// `>>`
+    Append,
+    /// `>|`
+    Clobber,
+    /// `<&`
+    DuplicateInput,
+    /// `>&`
+    DuplicateOutput,
+    /// `<>`
+    ReadWrite,
+    /// `<<`
+    HereDoc,
+    /// `<<-`, which strips leading tabs from the here-document.
+    HereDocStripTabs,
+    /// `&>`, which redirects both standard output and standard error. This is not POSIX, but is
+    /// supported by bash.
+    OutputAndError,
+    /// `&>>`, which appends both standard output and standard error. This is not POSIX, but is
+    /// supported by bash.
+    AppendOutputAndError,
+}
+
+/// A single word, made up of literal text, quoted text and expansions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Word<'a> {
+    /// The text of the word, as written.
+    pub text: &'a str,
+
+    /// The parts of the word, in order.
+    pub parts: Vec<WordPart<'a>>,
+}
+
+/// A part of a `Word`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WordPart<'a> {
+    /// Text outside of quotes, or literal text within double quotes.
+    Literal(&'a str),
+
+    /// A character escaped with a backslash, without the backslash.
+    Escaped(&'a str),
+
+    /// The contents of single quotes.
+    SingleQuoted(&'a str),
+
+    /// The parts within double quotes.
+    DoubleQuoted(Vec<WordPart<'a>>),
+
+    /// A parameter expansion, such as `$HOME`, `$1` or `${VERSION:-1.0}`.
+    Parameter {
+        /// The text of the expansion.
+        text: &'a str,
+        /// The name of the parameter.
+        name: &'a str,
+        /// Whatever follows the name within braces, such as `:-1.0`.
+        modifier: Option<&'a str>,
+    },
+
+    /// A command substitution, written as either `$(...)` or `` `...` ``.
+    CommandSubstitution {
+        /// The text of the substitution.
+        text: &'a str,
+        /// The script whose output is substituted.
+        script: Script<'a>,
+    },
+
+    /// An arithmetic expansion, such as `$((1 + 2))`.
+    Arithmetic {
+        /// The text of the expansion.
+        text: &'a str,
+        /// The expression within the parentheses.
+        expression: &'a str,
+    },
+}
+
+/// An error encountered while parsing a shell script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShellError {
+    offset: usize,
+    kind: ShellErrorKind,
+}
+
+/// The different kinds of errors which may be encountered while parsing a shell script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShellErrorKind {
+    /// The script ended within the given construct, such as a double quote.
+    Unterminated(&'static str),
+
+    /// Something other than what the grammar allows was found, or the script ended early.
+    Unexpected {
+        expected: &'static str,
+        found: Option<String>,
+    },
+}
+
+impl ShellError {
+    /// The byte offset within the script at which the error was found.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The kind of error which was encountered.
+    pub fn kind(&self) -> &ShellErrorKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "offset {}: {}", self.offset, self.kind)
+    }
+}
+
+impl fmt::Display for ShellErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShellErrorKind::Unterminated(what) => write!(f, "unterminated {}", what),
+            ShellErrorKind::Unexpected{expected, found: Some(found)} => write!(f, "expected {}, found {}", expected, found),
+            ShellErrorKind::Unexpected{expected, found: None} => write!(f, "expected {}, found end of script", expected),
+        }
+    }
+}
+
+impl error::Error for ShellError {}
+
+/// Parse a shell script.
+pub fn parse(script: &str) -> Result<Script<'_>, ShellError> {
+    let mut parser = Parser{src: script, pos: 0};
+    let parsed = parser.script()?;
+    match parser.at_end() {
+        true => Ok(parsed),
+        false => Err(parser.unexpected("end of script")),
+    }
+}
+
+/// The arguments of the `SHELL` in effect at each instruction of the Dockerfile, or `None` where
+/// Docker's default shell is in effect.
+///
+/// Each stage starts with the `SHELL` in effect at the end of the stage it is built `FROM`, if
+/// it is built from another stage.
+pub(crate) fn effective_shells(dockerfile: &Dockerfile) -> Vec<Option<Vec<String>>> {
+    let stages = dockerfile.stages();
+    let mut shells = vec![None; dockerfile.instructions().len()];
+    for stage in &stages {
+        let mut shell = match stage::resolve(&stages, stage.base(), EdgeKind::From) {
+            Some(Ok(base)) if base < stage.index() => shells[stages[base].range().end - 1].clone(),
+            _ => None,
+        };
+        for idx in stage.range() {
+            let instruction = &dockerfile.instructions()[idx];
+            if let (Instruction::Shell(_), Some(Form::Json(args))) = (instruction, instruction.form()) {
+                shell = Some(args);
+            }
+            shells[idx] = shell.clone();
+        }
+    }
+    shells
+}
+
+/// Whether the given `SHELL` arguments run a POSIX shell, such as `sh` or `bash`, rather than
+/// something like PowerShell or `cmd`.
+pub(crate) fn is_posix_shell(shell: &[String]) -> bool {
+    let program = match shell.first() {
+        Some(program) => program.rsplit(['/', '\\']).next().unwrap_or(program).to_ascii_lowercase(),
+        None => return false,
+    };
+    let program = program.strip_suffix(".exe").unwrap_or(&program);
+    ["sh", "bash", "dash", "ash", "ksh", "mksh", "zsh", "busybox"].contains(&program)
+}
+
+impl Instruction {
+    /// Parse the shell form arguments of a `RUN`, `CMD` or `ENTRYPOINT` as a shell script.
+    ///
+    /// Returns `None` for every other instruction, and for arguments in JSON form.
+    pub fn shell(&self) -> Option<Result<Script<'_>, ShellError>> {
+        match self {
+            Instruction::Cmd(_) | Instruction::Entrypoint(_) | Instruction::Run(_) => (),
+            _ => return None,
+        }
+        match self.form()? {
+            Form::Shell(script) => Some(parse(script)),
+            Form::Json(_) => None,
+        }
+    }
+}
+
+impl<'a> Script<'a> {
+    /// Every pipeline of this script, including those within compound commands and command
+    /// substitutions, in depth-first order.
+    pub fn pipelines(&self) -> Vec<&Pipeline<'a>> {
+        let mut pipelines = vec![];
+        for pipeline in self.lists.iter().flat_map(AndOrList::pipelines) {
+            pipelines.push(pipeline);
+            for command in &pipeline.commands {
+                for script in command.scripts() {
+                    pipelines.extend(script.pipelines());
+                }
+            }
+        }
+        pipelines
+    }
+
+    /// Every command of this script, including those within compound commands and command
+    /// substitutions, in depth-first order.
+    pub fn commands(&self) -> Vec<&Command<'a>> {
+        let mut commands = vec![];
+        for pipeline in self.lists.iter().flat_map(AndOrList::pipelines) {
+            for command in &pipeline.commands {
+                commands.push(command);
+                for script in command.scripts() {
+                    commands.extend(script.commands());
+                }
+            }
+        }
+        commands
+    }
+}
+
+impl<'a> AndOrList<'a> {
+    /// The pipelines of this list, in order.
+    pub fn pipelines(&self) -> impl Iterator<Item=&Pipeline<'a>> {
+        Some(&self.first).into_iter().chain(self.rest.iter().map(|(_, pipeline)| pipeline))
+    }
+}
+
+impl<'a> Command<'a> {
+    /// The words of a simple command, beginning with its name, or nothing for any other command.
+    pub fn words(&self) -> &[Word<'a>] {
+        match &self.kind {
+            CommandKind::Simple{words, ..} => words,
+            _ => &[],
+        }
+    }
+
+    /// The name of a simple command, if it can be known without expanding anything.
+    pub fn name(&self) -> Option<String> {
+        self.words().first()?.literal()
+    }
+
+    /// The arguments of a simple command, which follow its name.
+    pub fn args(&self) -> &[Word<'a>] {
+        self.words().get(1..).unwrap_or(&[])
+    }
+
+    /// The scripts nested directly within this command, as bodies, conditions and command
+    /// substitutions.
+    fn scripts(&self) -> Vec<&Script<'a>> {
+        let mut scripts = vec![];
+        let mut words: Vec<&Word<'a>> = self.redirects.iter().map(|redirect| &redirect.target).collect();
+        match &self.kind {
+            CommandKind::Simple{assignments, words: simple} => {
+                words.extend(assignments.iter().map(|assignment| &assignment.value));
+                words.extend(simple);
+            }
+            CommandKind::Subshell(body) | CommandKind::Group(body) => scripts.push(body),
+            CommandKind::If{branches, otherwise} => {
+                for (condition, body) in branches {
+                    scripts.push(condition);
+                    scripts.push(body);
+                }
+                scripts.extend(otherwise);
+            }
+            CommandKind::While{condition, body, ..} => scripts.extend([condition, body]),
+            CommandKind::For{items, body, ..} => {
+                words.extend(items.iter().flatten());
+                scripts.push(body);
+            }
+            CommandKind::Case{word, arms} => {
+                words.push(word);
+                scripts.extend(arms.iter().map(|arm| &arm.body));
+            }
+            CommandKind::Function{body, ..} => scripts.extend(body.scripts()),
+        }
+        for word in words {
+            collect_substitutions(&word.parts, &mut scripts);
+        }
+        scripts
+    }
+}
+
+impl<'a> Word<'a> {
+    /// The value of this word with its quotes removed, if it contains no expansions.
+    pub fn literal(&self) -> Option<String> {
+        let mut value = String::new();
+        match unquote(&self.parts, &mut value, false) {
+            true => Some(value),
+            false => None,
+        }
+    }
+
+    /// The value of this word with its quotes removed, leaving any expansions as written.
+    pub fn unquoted(&self) -> String {
+        let mut value = String::new();
+        unquote(&self.parts, &mut value, true);
+        value
+    }
+
+    /// Whether this word contains any expansions.
+    pub fn is_expanded(&self) -> bool {
+        self.literal().is_none()
+    }
+}
+
+// The `Display` impls write a canonical rendering of the syntax tree, on a single line with
+// single spaces between words and operators, and without comments. Words are written as they
+// were, quotes and all.
+
+impl fmt::Display for Script<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, list) in self.lists.iter().enumerate() {
+            match idx {
+                0 => (),
+                _ if self.lists[idx - 1].background => f.write_str(" ")?,
+                _ => f.write_str("; ")?,
+            }
+            write!(f, "{}", list)?;
+            if list.background {
+                f.write_str(" &")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOrList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (op, pipeline) in &self.rest {
+            match op {
+                LogicalOp::And => write!(f, " && {}", pipeline)?,
+                LogicalOp::Or => write!(f, " || {}", pipeline)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Pipeline<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negated {
+            f.write_str("! ")?;
+        }
+        for (idx, command) in self.commands.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(" | ")?;
+            }
+            write!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Command<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /// Write a script nested in a compound command, followed by the `;` ending it unless it
+        /// already ends with `&`.
+        fn nested(f: &mut fmt::Formatter, script: &Script<'_>) -> fmt::Result {
+            match script.lists.last() {
+                Some(list) if list.background => write!(f, "{}", script),
+                _ => write!(f, "{};", script),
+            }
+        }
+
+        let mut words = vec![];
+        match &self.kind {
+            CommandKind::Simple{assignments, words: simple} => {
+                words.extend(assignments.iter().map(|assignment| assignment.text));
+                words.extend(simple.iter().map(|word| word.text));
+            }
+            CommandKind::Subshell(script) => write!(f, "({})", script)?,
+            CommandKind::Group(script) => {
+                f.write_str("{ ")?;
+                nested(f, script)?;
+                f.write_str(" }")?;
+            }
+            CommandKind::If{branches, otherwise} => {
+                for (idx, (condition, body)) in branches.iter().enumerate() {
+                    f.write_str(if idx == 0 { "if " } else { " elif " })?;
+                    nested(f, condition)?;
+                    f.write_str(" then ")?;
+                    nested(f, body)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    f.write_str(" else ")?;
+                    nested(f, otherwise)?;
+                }
+                f.write_str(" fi")?;
+            }
+            CommandKind::While{until, condition, body} => {
+                f.write_str(if *until { "until " } else { "while " })?;
+                nested(f, condition)?;
+                f.write_str(" do ")?;
+                nested(f, body)?;
+                f.write_str(" done")?;
+            }
+            CommandKind::For{variable, items, body} => {
+                write!(f, "for {}", variable)?;
+                if let Some(items) = items {
+                    f.write_str(" in")?;
+                    for item in items {
+                        write!(f, " {}", item.text)?;
+                    }
+                }
+                f.write_str("; do ")?;
+                nested(f, body)?;
+                f.write_str(" done")?;
+            }
+            CommandKind::Case{word, arms} => {
+                write!(f, "case {} in", word.text)?;
+                for arm in arms {
+                    let patterns: Vec<_> = arm.patterns.iter().map(|pattern| pattern.text).collect();
+                    write!(f, " {})", patterns.join(" | "))?;
+                    if !arm.body.lists.is_empty() {
+                        write!(f, " {}", arm.body)?;
+                    }
+                    f.write_str(" ;;")?;
+                }
+                f.write_str(" esac")?;
+            }
+            CommandKind::Function{name, body} => write!(f, "{}() {}", name, body)?,
+        }
+        let redirects = self.redirects.iter().map(|redirect| redirect.to_string());
+        let words: Vec<_> = words.into_iter().map(str::to_string).chain(redirects).collect();
+        if !words.is_empty() && !matches!(self.kind, CommandKind::Simple{..}) {
+            f.write_str(" ")?;
+        }
+        f.write_str(&words.join(" "))
+    }
+}
+
+impl fmt::Display for Redirect<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(fd) = self.fd {
+            write!(f, "{}", fd)?;
+        }
+        write!(f, "{}{}", self.operator, self.target.text)
+    }
+}
+
+impl fmt::Display for RedirectOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (op, _) = REDIRECTS.iter().find(|(_, operator)| operator == self).expect("every operator is listed");
+        f.write_str(op)
+    }
+}
+
+/// Append the unquoted value of the given parts to `out`, returning whether they contain no
+/// expansions. Expansions are appended as written when `expansions` is set.
+fn unquote(parts: &[WordPart<'_>], out: &mut String, expansions: bool) -> bool {
+    let mut literal = true;
+    for part in parts {
+        match part {
+            WordPart::Literal(text) | WordPart::Escaped(text) | WordPart::SingleQuoted(text) => out.push_str(text),
+            WordPart::DoubleQuoted(parts) => literal &= unquote(parts, out, expansions),
+            WordPart::Parameter{text, ..} | WordPart::CommandSubstitution{text, ..} | WordPart::Arithmetic{text, ..} => {
+                literal = false;
+                if expansions {
+                    out.push_str(text);
+                }
+            }
+        }
+    }
+    literal
+}
+
+/// Collect the scripts of the command substitutions within the given parts.
+fn collect_substitutions<'s, 'a>(parts: &'s [WordPart<'a>], scripts: &mut Vec<&'s Script<'a>>) {
+    for part in parts {
+        match part {
+            WordPart::CommandSubstitution{script, ..} => scripts.push(script),
+            WordPart::DoubleQuoted(parts) => collect_substitutions(parts, scripts),
+            _ => (),
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Parser ////////////////////////////////////////////////////////////////////////////////////////
+
+/// The operators of the shell, longest first so that each is matched in full.
+const OPERATORS: &[&str] = &["&&", "||", ";;", "|", "&", ";", "(", ")", "\n"];
+
+/// The redirection operators, longest first so that each is matched in full.
+const REDIRECTS: &[(&str, RedirectOp)] = &[
+    ("&>>", RedirectOp::AppendOutputAndError),
+    ("<<-", RedirectOp::HereDocStripTabs),
+    ("&>", RedirectOp::OutputAndError),
+    ("<<", RedirectOp::HereDoc),
+    ("<&", RedirectOp::DuplicateInput),
+    ("<>", RedirectOp::ReadWrite),
+    (">>", RedirectOp::Append),
+    (">&", RedirectOp::DuplicateOutput),
+    (">|", RedirectOp::Clobber),
+    ("<", RedirectOp::Input),
+    (">", RedirectOp::Output),
+];
+
+/// The reserved words, which are recognized only where a command may begin.
+const RESERVED: &[&str] = &["!", "{", "}", "case", "do", "done", "elif", "else", "esac", "fi", "for", "if", "in", "then", "until", "while"];
+
+/// The reserved words which end a script nested within a compound command.
+const TERMINATORS: &[&str] = &["}", "do", "done", "elif", "else", "esac", "fi", "then"];
+
+/// Whether the given character ends an unquoted word.
+fn is_delimiter(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n' | ';' | '&' | '|' | '(' | ')' | '<' | '>')
+}
+
+/// Whether the given character may appear within the name of a variable.
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// A recursive descent parser over a shell script.
+///
+/// Tokens are recognized on demand at the current position. Peeking never moves the position,
+/// so the text of every node ends at the last token consumed.
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Parse a script, stopping at the end of the input or at whatever ends an enclosing
+    /// construct, such as `)` or `fi`.
+    fn script(&mut self) -> Result<Script<'a>, ShellError> {
+        self.skip_newlines();
+        let start = self.start();
+        let mut end = start;
+        let mut lists = vec![];
+        while !self.at_end()
+            && !matches!(self.peek_op(), Some(")") | Some(";;"))
+            && !self.peek_reserved().is_some_and(|word| TERMINATORS.contains(&word))
+        {
+            let mut list = self.and_or()?;
+            end = self.pos;
+            let terminated = match self.peek_op() {
+                Some("&") => {
+                    list.background = true;
+                    true
+                }
+                Some(";") | Some("\n") => true,
+                _ => false,
+            };
+            lists.push(list);
+            if !terminated {
+                break;
+            }
+            self.consume(self.peek_op().unwrap_or_default());
+            self.skip_newlines();
+        }
+        Ok(Script{text: &self.src[start..end], lists})
+    }
+
+    fn and_or(&mut self) -> Result<AndOrList<'a>, ShellError> {
+        let start = self.start();
+        let first = self.pipeline()?;
+        let mut rest = vec![];
+        while let Some(op) = self.peek_op().filter(|op| *op == "&&" || *op == "||") {
+            self.consume(op);
+            self.skip_newlines();
+            let op = if op == "&&" { LogicalOp::And } else { LogicalOp::Or };
+            rest.push((op, self.pipeline()?));
+        }
+        Ok(AndOrList{text: &self.src[start..self.pos], first, rest, background: false})
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline<'a>, ShellError> {
+        let start = self.start();
+        let negated = self.peek_reserved() == Some("!");
+        if negated {
+            self.consume("!");
+        }
+        let mut commands = vec![self.command()?];
+        while self.peek_op() == Some("|") {
+            self.consume("|");
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline{text: &self.src[start..self.pos], negated, commands})
+    }
+
+    fn command(&mut self) -> Result<Command<'a>, ShellError> {
+        let start = self.start();
+        let kind = match (self.peek_op(), self.peek_reserved()) {
+            (Some("("), _) => {
+                self.consume("(");
+                let body = self.script()?;
+                self.expect_op(")")?;
+                CommandKind::Subshell(body)
+            }
+            (_, Some("{")) => {
+                self.consume("{");
+                let body = self.script()?;
+                self.expect_reserved("}")?;
+                CommandKind::Group(body)
+            }
+            (_, Some("if")) => self.if_clause()?,
+            (_, Some(keyword @ "while")) | (_, Some(keyword @ "until")) => {
+                self.consume(keyword);
+                let condition = self.script()?;
+                self.expect_reserved("do")?;
+                let body = self.script()?;
+                self.expect_reserved("done")?;
+                CommandKind::While{until: keyword == "until", condition, body}
+            }
+            (_, Some("for")) => self.for_clause()?,
+            (_, Some("case")) => self.case_clause()?,
+            _ => return self.simple_command(),
+        };
+        let redirects = self.redirects()?;
+        Ok(Command{text: &self.src[start..self.pos], kind, redirects})
+    }
+
+    fn simple_command(&mut self) -> Result<Command<'a>, ShellError> {
+        let start = self.start();
+        let (mut assignments, mut words, mut redirects) = (vec![], vec![], vec![]);
+        loop {
+            if let Some(redirect) = self.redirect()? {
+                redirects.push(redirect);
+                continue;
+            }
+            if self.at_end() || self.peek_op().is_some() {
+                break;
+            }
+            let word = self.word()?;
+            match assignment(&word) {
+                Some(assignment) if words.is_empty() => assignments.push(assignment),
+                _ => words.push(word),
+            }
+        }
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            return Err(self.unexpected("a command"));
+        }
+
+        // A lone word followed by `()` begins a function definition.
+        if let ([name], true, true) = (&words[..], assignments.is_empty(), redirects.is_empty()) {
+            let before = self.pos;
+            if self.peek_op() == Some("(") {
+                self.consume("(");
+                if self.peek_op() == Some(")") {
+                    self.consume(")");
+                    self.skip_newlines();
+                    let body = Box::new(self.command()?);
+                    return Ok(Command{text: &self.src[start..self.pos], kind: CommandKind::Function{name: name.text, body}, redirects});
+                }
+                self.pos = before;
+            }
+        }
+        Ok(Command{text: &self.src[start..self.pos], kind: CommandKind::Simple{assignments, words}, redirects})
+    }
+
+    fn if_clause(&mut self) -> Result<CommandKind<'a>, ShellError> {
+        self.consume("if");
+        let mut branches = vec![];
+        let mut otherwise = None;
+        loop {
+            let condition = self.script()?;
+            self.expect_reserved("then")?;
+            branches.push((condition, self.script()?));
+            match self.peek_reserved() {
+                Some("elif") => self.consume("elif"),
+                Some("else") => {
+                    self.consume("else");
+                    otherwise = Some(self.script()?);
+                    self.expect_reserved("fi")?;
+                    break;
+                }
+                _ => {
+                    self.expect_reserved("fi")?;
+                    break;
+                }
+            }
+        }
+        Ok(CommandKind::If{branches, otherwise})
+    }
+
+    fn for_clause(&mut self) -> Result<CommandKind<'a>, ShellError> {
+        self.consume("for");
+        let variable = self.required_word("a variable name")?.text;
+        self.skip_newlines();
+        let items = match self.peek_reserved() {
+            Some("in") => {
+                self.consume("in");
+                let mut items = vec![];
+                while !self.at_end() && self.peek_op().is_none() {
+                    items.push(self.word()?);
+                }
+                Some(items)
+            }
+            _ => None,
+        };
+        if self.peek_op() == Some(";") {
+            self.consume(";");
+        }
+        self.skip_newlines();
+        self.expect_reserved("do")?;
+        let body = self.script()?;
+        self.expect_reserved("done")?;
+        Ok(CommandKind::For{variable, items, body})
+    }
+
+    fn case_clause(&mut self) -> Result<CommandKind<'a>, ShellError> {
+        self.consume("case");
+        let word = self.required_word("a word")?;
+        self.skip_newlines();
+        self.expect_reserved("in")?;
+        self.skip_newlines();
+        let mut arms = vec![];
+        while self.peek_reserved() != Some("esac") {
+            if self.peek_op() == Some("(") {
+                self.consume("(");
+            }
+            let mut patterns = vec![self.required_word("a pattern")?];
+            while self.peek_op() == Some("|") {
+                self.consume("|");
+                patterns.push(self.required_word("a pattern")?);
+            }
+            self.expect_op(")")?;
+            let body = self.script()?;
+            arms.push(CaseArm{patterns, body});
+            if self.peek_op() == Some(";;") {
+                self.consume(";;");
+            } else if self.peek_reserved() != Some("esac") {
+                return Err(self.unexpected("esac"));
+            }
+            self.skip_newlines();
+        }
+        self.consume("esac");
+        Ok(CommandKind::Case{word, arms})
+    }
+
+    fn redirects(&mut self) -> Result<Vec<Redirect<'a>>, ShellError> {
+        let mut redirects = vec![];
+        while let Some(redirect) = self.redirect()? {
+            redirects.push(redirect);
+        }
+        Ok(redirects)
+    }
+
+    /// Parse a redirection, if one begins at the current position.
+    fn redirect(&mut self) -> Result<Option<Redirect<'a>>, ShellError> {
+        let start = self.start();
+        let rest = &self.src[start..];
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let (op, operator) = match REDIRECTS.iter().find(|(op, _)| rest[digits..].starts_with(op)) {
+            Some(&(op, _)) if digits > 0 && op.starts_with('&') => return Ok(None),
+            Some(&redirect) => redirect,
+            None => return Ok(None),
+        };
+        let fd = rest[..digits].parse().ok();
+        self.pos = start + digits + op.len();
+        let target = self.required_word("a redirection target")?;
+        Ok(Some(Redirect{text: &self.src[start..self.pos], fd, operator, target}))
+    }
+
+    /// Parse a word, failing with the given expectation if none begins at the current position.
+    fn required_word(&mut self, expected: &'static str) -> Result<Word<'a>, ShellError> {
+        if self.at_end() || self.peek_op().is_some() || self.src[self.start()..].starts_with(['<', '>']) {
+            return Err(self.unexpected(expected));
+        }
+        self.word()
+    }
+
+    /// Parse the word which begins at the current position.
+    fn word(&mut self) -> Result<Word<'a>, ShellError> {
+        self.pos = self.start();
+        let start = self.pos;
+        let mut parts = vec![];
+        while let Some(c) = self.peek_char() {
+            match c {
+                c if is_delimiter(c) => break,
+                '\\' => parts.extend(self.escape(false)),
+                '\'' => parts.push(self.single_quoted()?),
+                '"' => parts.push(self.double_quoted()?),
+                '$' => parts.push(self.dollar()?),
+                '`' => parts.push(self.backquoted()?),
+                _ => parts.push(self.literal(|c| is_delimiter(c) || matches!(c, '\\' | '\'' | '"' | '$' | '`'))),
+            }
+        }
+        Ok(Word{text: &self.src[start..self.pos], parts})
+    }
+
+    /// Parse literal text up to the next character matching `stop`.
+    fn literal<F: Fn(char) -> bool>(&mut self, stop: F) -> WordPart<'a> {
+        let rest = &self.src[self.pos..];
+        let len = rest.find(stop).unwrap_or(rest.len());
+        self.pos += len;
+        WordPart::Literal(&rest[..len])
+    }
+
+    /// Parse a backslash escape. Within double quotes, a backslash escapes only `$`, `` ` ``, `"`,
+    /// `\` and newlines, and is otherwise literal.
+    fn escape(&mut self, quoted: bool) -> Option<WordPart<'a>> {
+        let escaped = self.src[self.pos + 1..].chars().next();
+        match escaped {
+            Some('\n') => {
+                self.pos += 2;
+                None
+            }
+            Some(c) if !quoted || matches!(c, '$' | '`' | '"' | '\\') => {
+                let start = self.pos + 1;
+                self.pos = start + c.len_utf8();
+                Some(WordPart::Escaped(&self.src[start..self.pos]))
+            }
+            _ => {
+                self.pos += 1;
+                Some(WordPart::Literal(&self.src[self.pos - 1..self.pos]))
+            }
+        }
+    }
+
+    fn single_quoted(&mut self) -> Result<WordPart<'a>, ShellError> {
+        let start = self.pos + 1;
+        let len = self.src[start..].find('\'').ok_or_else(|| self.error(ShellErrorKind::Unterminated("single quote")))?;
+        self.pos = start + len + 1;
+        Ok(WordPart::SingleQuoted(&self.src[start..start + len]))
+    }
+
+    fn double_quoted(&mut self) -> Result<WordPart<'a>, ShellError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut parts = vec![];
+        loop {
+            match self.peek_char() {
+                None => {
+                    self.pos = start;
+                    return Err(self.error(ShellErrorKind::Unterminated("double quote")));
+                }
+                Some('"') => break,
+                Some('\\') => parts.extend(self.escape(true)),
+                Some('$') => parts.push(self.dollar()?),
+                Some('`') => parts.push(self.backquoted()?),
+                Some(_) => parts.push(self.literal(|c| matches!(c, '"' | '\\' | '$' | '`'))),
+            }
+        }
+        self.pos += 1;
+        Ok(WordPart::DoubleQuoted(parts))
+    }
+
+    /// Parse whatever begins with a `$`, which is a literal `$` unless an expansion follows.
+    fn dollar(&mut self) -> Result<WordPart<'a>, ShellError> {
+        let start = self.pos;
+        let rest = &self.src[start..];
+        let bytes = rest.as_bytes();
+        if rest.starts_with("$((") {
+            let mut depth = 0;
+            for idx in 3..bytes.len() {
+                match bytes[idx] {
+                    b'(' => depth += 1,
+                    b')' if depth > 0 => depth -= 1,
+                    b')' if bytes.get(idx + 1) == Some(&b')') => {
+                        self.pos = start + idx + 2;
+                        return Ok(WordPart::Arithmetic{text: &rest[..idx + 2], expression: &rest[3..idx]});
+                    }
+                    b')' => break,
+                    _ => (),
+                }
+            }
+        }
+        // As in sh, a `$((` which is not arithmetic is a command substitution of a subshell, as in
+        // `$((cd /tmp; ls) )`.
+        if rest.starts_with("$(") {
+            self.pos += 2;
+            let script = self.script()?;
+            if self.at_end() {
+                self.pos = start;
+                return Err(self.error(ShellErrorKind::Unterminated("command substitution")));
+            }
+            self.expect_op(")")?;
+            Ok(WordPart::CommandSubstitution{text: &self.src[start..self.pos], script})
+        } else if rest.starts_with("${") {
+            let (mut depth, mut quote, mut idx) = (0, None, 2);
+            loop {
+                match (quote, bytes.get(idx)) {
+                    (_, None) => return Err(self.error(ShellErrorKind::Unterminated("parameter expansion"))),
+                    (Some(q), Some(&c)) if q == c => quote = None,
+                    (Some(b'\''), _) => (),
+                    (_, Some(b'\\')) => idx += 1,
+                    (Some(_), _) => (),
+                    (None, Some(&c)) if c == b'"' || c == b'\'' => quote = Some(c),
+                    (None, Some(b'{')) => depth += 1,
+                    (None, Some(b'}')) if depth == 0 => break,
+                    (None, Some(b'}')) => depth -= 1,
+                    _ => (),
+                }
+                idx += 1;
+            }
+            let inner = &rest[2..idx];
+            let len = match inner.chars().next() {
+                Some(c) if c.is_ascii_digit() => inner.find(|c: char| !c.is_ascii_digit()).unwrap_or(inner.len()),
+                Some(c) if is_name_char(c) => inner.find(|c| !is_name_char(c)).unwrap_or(inner.len()),
+                Some(c) => c.len_utf8(),
+                None => 0,
+            };
+            self.pos = start + idx + 1;
+            let modifier = Some(&inner[len..]).filter(|modifier| !modifier.is_empty());
+            Ok(WordPart::Parameter{text: &rest[..idx + 1], name: &inner[..len], modifier})
+        } else {
+            let len = match rest[1..].chars().next() {
+                Some(c) if is_name_char(c) && !c.is_ascii_digit() => rest[1..].find(|c| !is_name_char(c)).unwrap_or(rest.len() - 1),
+                Some(c) if c.is_ascii_digit() || "@*#?$!-".contains(c) => 1,
+                _ => {
+                    self.pos += 1;
+                    return Ok(WordPart::Literal(&rest[..1]));
+                }
+            };
+            self.pos = start + 1 + len;
+            Ok(WordPart::Parameter{text: &rest[..1 + len], name: &rest[1..1 + len], modifier: None})
+        }
+    }
+
+    fn backquoted(&mut self) -> Result<WordPart<'a>, ShellError> {
+        let start = self.pos;
+        let bytes = self.src.as_bytes();
+        let mut end = start + 1;
+        while end < bytes.len() && bytes[end] != b'`' {
+            end += if bytes[end] == b'\\' { 2 } else { 1 };
+        }
+        if end >= bytes.len() {
+            return Err(self.error(ShellErrorKind::Unterminated("backquote")));
+        }
+        // Parse the contents with a parser which sees the input only up to the closing backquote.
+        let mut inner = Parser{src: &self.src[..end], pos: start + 1};
+        let script = inner.script()?;
+        if !inner.at_end() {
+            return Err(inner.unexpected("`"));
+        }
+        self.pos = end + 1;
+        Ok(WordPart::CommandSubstitution{text: &self.src[start..self.pos], script})
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////////////
+    // Tokens ////////////////////////////////////////////////////////////////////////////////////
+
+    /// The position of the next token, following any blanks, line continuations and comments.
+    fn start(&self) -> usize {
+        let bytes = self.src.as_bytes();
+        let mut idx = self.pos;
+        loop {
+            match bytes.get(idx) {
+                Some(b' ') | Some(b'\t') | Some(b'\r') => idx += 1,
+                Some(b'\\') if bytes.get(idx + 1) == Some(&b'\n') => idx += 2,
+                Some(b'#') => idx += self.src[idx..].find('\n').unwrap_or(self.src.len() - idx),
+                _ => return idx,
+            }
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.start() >= self.src.len()
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    /// The operator at the next token, if any. Redirection operators are not included.
+    fn peek_op(&self) -> Option<&'static str> {
+        let rest = &self.src[self.start()..];
+        if REDIRECTS.iter().any(|(op, _)| op.starts_with('&') && rest.starts_with(op)) {
+            return None;
+        }
+        OPERATORS.iter().find(|op| rest.starts_with(*op)).copied()
+    }
+
+    /// The reserved word at the next token, if any.
+    fn peek_reserved(&self) -> Option<&'static str> {
+        let rest = &self.src[self.start()..];
+        let word = &rest[..rest.find(is_delimiter).unwrap_or(rest.len())];
+        RESERVED.iter().find(|reserved| **reserved == word).copied()
+    }
+
+    /// Consume the given operator or reserved word, which must be the next token.
+    fn consume(&mut self, token: &str) {
+        self.pos = self.start() + token.len();
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_op() == Some("\n") {
+            self.consume("\n");
+        }
+    }
+
+    fn expect_op(&mut self, op: &'static str) -> Result<(), ShellError> {
+        if self.peek_op() != Some(op) {
+            return Err(self.unexpected(op));
+        }
+        self.consume(op);
+        Ok(())
+    }
+
+    fn expect_reserved(&mut self, word: &'static str) -> Result<(), ShellError> {
+        if self.peek_reserved() != Some(word) {
+            return Err(self.unexpected(word));
+        }
+        self.consume(word);
+        Ok(())
+    }
+
+    /// An error at the current position.
+    fn error(&self, kind: ShellErrorKind) -> ShellError {
+        ShellError{offset: self.pos, kind}
+    }
+
+    /// An error for finding the next token when something else was expected.
+    fn unexpected(&self, expected: &'static str) -> ShellError {
+        let start = self.start();
+        let rest = &self.src[start..];
+        let found = match self.peek_op() {
+            _ if rest.is_empty() => None,
+            Some("\n") => Some("newline".to_string()),
+            Some(op) => Some(op.to_string()),
+            None => {
+                let len = rest.find(is_delimiter).filter(|&len| len > 0).unwrap_or_else(|| rest.chars().next().map_or(0, char::len_utf8));
+                Some(rest[..len].to_string())
+            }
+        };
+        ShellError{offset: start, kind: ShellErrorKind::Unexpected{expected, found}}
+    }
+}
+
+/// Interpret a word preceding the name of a simple command as an assignment, if it begins with a
+/// valid variable name followed by `=`.
+fn assignment<'a>(word: &Word<'a>) -> Option<Assignment<'a>> {
+    let literal = match word.parts.first() {
+        Some(WordPart::Literal(literal)) => *literal,
+        _ => return None,
+    };
+    let eq = literal.find('=')?;
+    let name = &literal[..eq];
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) || !name.chars().all(is_name_char) {
+        return None;
+    }
+    let mut parts = word.parts.clone();
+    match &literal[eq + 1..] {
+        "" => parts.remove(0),
+        rest => std::mem::replace(&mut parts[0], WordPart::Literal(rest)),
+    };
+    Some(Assignment{text: word.text, name, value: Word{text: &word.text[eq + 1..], parts}})
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instructions::Run;
+
+    /// The words of every simple command of the script, unquoted.
+    fn commands(script: &str) -> Vec<Vec<String>> {
+        let script = parse(script).unwrap();
+        script.commands().iter().map(|command| command.words().iter().map(Word::unquoted).collect()).collect()
+    }
+
+    #[test]
+    fn parse_lists_and_pipelines() {
+        let script = parse("apt-get update && apt-get install -y curl || exit 1; curl -fsSL https://example.com | sh & wait").unwrap();
+        assert_eq!(script.lists.len(), 3);
+        let first = &script.lists[0];
+        assert_eq!(first.text, "apt-get update && apt-get install -y curl || exit 1");
+        assert_eq!(first.first.text, "apt-get update");
+        assert_eq!(first.rest.iter().map(|(op, pipeline)| (*op, pipeline.text)).collect::<Vec<_>>(), vec![
+            (LogicalOp::And, "apt-get install -y curl"),
+            (LogicalOp::Or, "exit 1"),
+        ]);
+        assert!(script.lists[1].background);
+        assert_eq!(script.lists[1].first.commands.iter().map(|command| command.text).collect::<Vec<_>>(), vec!["curl -fsSL https://example.com", "sh"]);
+        assert_eq!(script.pipelines().len(), 5);
+    }
+
+    #[test]
+    fn parse_quoting_and_expansions() {
+        let script = parse(r#"FOO=bar echo "a $HOME ${V:-1.0}" 'b c' d\ e $(cat /f) `id -u` $((1 + 2)) $"#).unwrap();
+        let command = &script.commands()[0];
+        let assignments = match &command.kind {
+            CommandKind::Simple{assignments, ..} => assignments,
+            kind => panic!("unexpected command {:?}", kind),
+        };
+        assert_eq!((assignments[0].name, assignments[0].value.literal()), ("FOO", Some("bar".to_string())));
+        assert_eq!(command.name(), Some("echo".to_string()));
+        let args: Vec<_> = command.args().iter().map(Word::unquoted).collect();
+        assert_eq!(args, vec!["a $HOME ${V:-1.0}", "b c", "d e", "$(cat /f)", "`id -u`", "$((1 + 2))", "$"]);
+        assert_eq!(command.args()[0].parts, vec![WordPart::DoubleQuoted(vec![
+            WordPart::Literal("a "),
+            WordPart::Parameter{text: "$HOME", name: "HOME", modifier: None},
+            WordPart::Literal(" "),
+            WordPart::Parameter{text: "${V:-1.0}", name: "V", modifier: Some(":-1.0")},
+        ])]);
+        assert!(command.args()[0].is_expanded());
+        assert_eq!(command.args()[2].literal(), Some("d e".to_string()));
+        assert_eq!(commands(r#"echo $(cat /f) `id -u`"#), vec![vec!["echo", "$(cat /f)", "`id -u`"], vec!["cat", "/f"], vec!["id", "-u"]]);
+        assert_eq!(commands("echo $((cd /tmp; ls) )"), vec![vec!["echo", "$((cd /tmp; ls) )"], vec![], vec!["cd", "/tmp"], vec!["ls"]]);
+    }
+
+    #[test]
+    fn parse_redirections() {
+        let script = parse("make 2>&1 >/dev/null <in &> all.log; cat <<-EOF").unwrap();
+        let redirects: Vec<_> = script.commands().iter()
+            .flat_map(|command| &command.redirects)
+            .map(|redirect| (redirect.fd, redirect.operator, redirect.target.text))
+            .collect();
+        assert_eq!(redirects, vec![
+            (Some(2), RedirectOp::DuplicateOutput, "1"),
+            (None, RedirectOp::Output, "/dev/null"),
+            (None, RedirectOp::Input, "in"),
+            (None, RedirectOp::OutputAndError, "all.log"),
+            (None, RedirectOp::HereDocStripTabs, "EOF"),
+        ]);
+        assert_eq!(script.commands()[0].words().len(), 1);
+    }
+
+    #[test]
+    fn parse_compound_commands() {
+        let script = r#"( cd /tmp && make ) > log; { a; b; }
+if [ -f x ]; then c; elif d; then e; else f; fi
+for x in 1 2; do g "$x"; done; while ! h; do i; done
+case "$1" in a|b) j ;; *) k ;; esac
+build() { l; }"#;
+        let names: Vec<_> = parse(script).unwrap().commands().iter().filter_map(|command| command.name()).collect();
+        assert_eq!(names, vec!["cd", "make", "a", "b", "[", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l"]);
+
+        let parsed = parse("for x in a b; do echo $x; done").unwrap();
+        match &parsed.commands()[0].kind {
+            CommandKind::For{variable, items: Some(items), ..} => assert_eq!((*variable, items.len()), ("x", 2)),
+            kind => panic!("unexpected command {:?}", kind),
+        }
+        assert_eq!(parse("( a ) 2>/dev/null").unwrap().commands()[0].redirects.len(), 1);
+    }
+
+    #[test]
+    fn parse_comments_and_continuations() {
+        assert_eq!(commands("a \\\n b # c && d\ne"), vec![vec!["a", "b"], vec!["e"]]);
+        assert_eq!(commands("echo a#b"), vec![vec!["echo", "a#b"]]);
+    }
+
+    #[test]
+    fn display_is_canonical() {
+        let canonical = |script| parse(script).unwrap().to_string();
+        assert_eq!(canonical("apt-get   update&&apt-get install -y  'a  b' ||  exit 1 ;echo  done &  wait"),
+            "apt-get update && apt-get install -y 'a  b' || exit 1; echo done & wait");
+        assert_eq!(canonical("FOO=1  >out  make 2> /dev/null  | ! tee  log"), "FOO=1 make >out 2>/dev/null | ! tee log");
+        assert_eq!(canonical("( cd /tmp&&make )>log ; {  a ; b & }"), "(cd /tmp && make) >log; { a; b & }");
+        assert_eq!(canonical("if  [ -f x ] ;then c\nelif d; then e ; else f;fi"), "if [ -f x ]; then c; elif d; then e; else f; fi");
+        assert_eq!(canonical("for x in 1  2; do g \"$x\" ; done;until h;do i;done"), "for x in 1 2; do g \"$x\"; done; until h; do i; done");
+        assert_eq!(canonical("case  $1 in a|b) j;; *) ;; esac # c"), "case $1 in a | b) j ;; *) ;; esac");
+        assert_eq!(canonical("build ( ) { l ; }"), "build() { l; }");
+
+        let script = "if [ -f x ]; then c; fi; for x in 1 2; do g; done; case $1 in a | b) j ;; esac; (a) >log";
+        assert_eq!(canonical(script), script);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |script| parse(script).unwrap_err().to_string();
+        assert_eq!(error("echo 'a"), "offset 5: unterminated single quote");
+        assert_eq!(error("echo \"a"), "offset 5: unterminated double quote");
+        assert_eq!(error("echo $(a"), "offset 5: unterminated command substitution");
+        assert_eq!(error("echo $((1 + 2"), "offset 13: expected ), found end of script");
+        assert_eq!(error("echo ${a"), "offset 5: unterminated parameter expansion");
+        assert_eq!(error("if a; then b"), "offset 12: expected fi, found end of script");
+        assert_eq!(error("a && && b"), "offset 5: expected a command, found &&");
+        assert_eq!(error("a )"), "offset 2: expected end of script, found )");
+        assert_eq!(error("a >"), "offset 3: expected a redirection target, found end of script");
+    }
+
+    #[test]
+    fn instruction_shell() {
+        let run: Instruction = Run::new("--mount=type=cache,target=/root/.cache pip install -r requirements.txt").into();
+        let script = run.shell().unwrap().unwrap();
+        assert_eq!(script.text, "pip install -r requirements.txt");
+        assert!(Instruction::from(Run::new(r#"["pip", "install"]"#)).shell().is_none());
+    }
+}
@@ -0,0 +1,695 @@
+use std::{
+    borrow::Cow,
+    error,
+    fmt,
+    ops::Range,
+    panic::Location,
+};
+
+use crate::{
+    builder::{
+        Dockerfile,
+        DockerfileBuilder,
+        Provenance,
+    },
+    flags::Flags,
+    fmt::format_flag,
+    instructions::{
+        From,
+        Instruction,
+    },
+    parser,
+    render::{
+        self,
+        split_words,
+    },
+};
+
+/// A single build stage of a Dockerfile.
+///
+/// A stage begins with a `FROM` instruction and runs up to the next one. Stages are a view over
+/// the instructions of a Dockerfile, obtained from `Dockerfile::stages`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stage<'a> {
+    index: usize,
+    dockerfile: &'a Dockerfile,
+    start: usize,
+    end: usize,
+}
+
+impl<'a> Stage<'a> {
+    /// The position of this stage among the stages of its Dockerfile, starting from 0.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The name given to this stage with `FROM <image> AS <name>`, if any.
+    pub fn name(&self) -> Option<&'a str> {
+        split_from(self.header()).1
+    }
+
+    /// The image this stage builds upon, which may be the name of an earlier stage.
+    pub fn base(&self) -> &'a str {
+        split_from(self.header()).0
+    }
+
+    /// The `FROM` instruction which begins this stage.
+    pub fn from(&self) -> &'a From {
+        match &self.dockerfile.instructions()[self.start] {
+            Instruction::From(from) => from,
+            _ => unreachable!("every stage begins with a FROM instruction"),
+        }
+    }
+
+    /// The instructions of this stage, following its `FROM` instruction.
+    pub fn instructions(&self) -> &'a [Instruction] {
+        &self.dockerfile.instructions()[self.start + 1..self.end]
+    }
+
+    /// The indices of this stage's instructions within its Dockerfile, including its `FROM`.
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// The Dockerfile to which this stage belongs.
+    pub(crate) fn dockerfile(&self) -> &'a Dockerfile {
+        self.dockerfile
+    }
+
+    /// The arguments of this stage's `FROM` instruction.
+    fn header(&self) -> &'a str {
+        self.dockerfile.instructions()[self.start].value()
+    }
+}
+
+impl Dockerfile {
+    /// The build stages of this Dockerfile, in order.
+    ///
+    /// Any parser directives and `ARG`s before the first `FROM` belong to no stage.
+    pub fn stages(&self) -> Vec<Stage<'_>> {
+        let starts: Vec<_> = self.instructions().iter().enumerate()
+            .filter(|(_, inst)| matches!(inst, Instruction::From(_)))
+            .map(|(idx, _)| idx)
+            .collect();
+        starts.iter().enumerate().map(|(index, &start)| {
+            let end = starts.get(index + 1).copied().unwrap_or_else(|| self.instructions().len());
+            Stage{index, dockerfile: self, start, end}
+        }).collect()
+    }
+
+    /// The index of the stage to which the instruction at the given index belongs.
+    ///
+    /// Returns `None` for instructions before the first `FROM`, and for out of bounds indices.
+    pub fn stage_of(&self, index: usize) -> Option<usize> {
+        if index >= self.instructions().len() {
+            return None;
+        }
+        self.instructions()[..=index].iter()
+            .filter(|inst| matches!(inst, Instruction::From(_)))
+            .count()
+            .checked_sub(1)
+    }
+
+    /// Check every reference this Dockerfile makes from one stage to another.
+    ///
+    /// This catches the mistakes Docker rejects: an invalid stage name, two stages sharing a name,
+    /// a `FROM`, `COPY --from` or `RUN --mount=from=` referring to its own stage or to a later
+    /// one, and a numeric `--from` index which names no stage. References which name no stage are
+    /// taken to be images, as Docker takes them, unless they were made from a `StageRef`, which
+    /// must name the stage it was taken from.
+    pub fn check_stage_refs(&self) -> Result<(), StageRefError> {
+        let stages = self.stages();
+        for (idx, stage) in stages.iter().enumerate() {
+            if let Some(name) = stage.name() {
+                if !is_valid_stage_name(name) {
+                    return Err(StageRefError::InvalidName(name.to_string()));
+                }
+                if stages[..idx].iter().any(|prev| prev.name().is_some_and(|prev| prev.eq_ignore_ascii_case(name))) {
+                    return Err(StageRefError::Duplicate(name.to_string()));
+                }
+            }
+        }
+        for stage in &stages {
+            for instruction in &self.instructions()[stage.range()] {
+                let handle = match instruction {
+                    Instruction::Copy(copy) => copy.stage_ref(),
+                    Instruction::From(from) => from.stage_ref(),
+                    _ => None,
+                };
+                if let Some(handle) = handle {
+                    let target = stages.get(handle.index()).and_then(|target| target.name());
+                    if !target.is_some_and(|name| name.eq_ignore_ascii_case(handle.name())) {
+                        let (reference, target) = (handle.name().to_string(), handle.index());
+                        return Err(StageRefError::ForeignStage{reference, stage: stage.index(), target});
+                    }
+                }
+                for (kind, reference) in stage_references(instruction) {
+                    match resolve(&stages, &reference, kind) {
+                        Some(Ok(target)) if target >= stage.index() => {
+                            return Err(StageRefError::Forward{reference, stage: stage.index(), target});
+                        }
+                        Some(Err(())) => return Err(StageRefError::Missing{reference, stage: stage.index()}),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Dockerfile {
+    /// Rename the stage named `old` to `new`, rewriting every reference later stages make to it.
+    ///
+    /// This fails if no stage is named `old`, if `new` is not a valid stage name, or if `new` is
+    /// already in use, either by another stage or as an image which would then refer to the
+    /// renamed stage instead. Names are compared case-insensitively, as Docker compares them.
+    pub fn rename_stage(&mut self, old: &str, new: &str) -> Result<(), RenameError> {
+        let stages = self.stages();
+        let target = stages.iter()
+            .find(|stage| stage.name().is_some_and(|name| name.eq_ignore_ascii_case(old)))
+            .ok_or_else(|| RenameError::NotFound(old.to_string()))?;
+        if !is_valid_stage_name(new) {
+            return Err(RenameError::InvalidName(new.to_string()));
+        }
+        let collides = stages.iter().any(|stage| stage.index() != target.index() && stage.name().is_some_and(|name| name.eq_ignore_ascii_case(new)));
+        if collides {
+            return Err(RenameError::StageCollision(new.to_string()));
+        }
+        for instruction in self.instructions() {
+            for (kind, reference) in stage_references(instruction) {
+                if reference.eq_ignore_ascii_case(new) && resolve(&stages, &reference, kind).is_none() {
+                    return Err(RenameError::ImageCollision(new.to_string()));
+                }
+            }
+        }
+
+        let (start, index) = (target.range().start, target.index());
+        let escape = render::escape_char(self);
+        let mut instructions = self.instructions.clone();
+        let alias = split_from(self.instructions[start].value()).1.expect("the renamed stage has a name");
+        instructions[start] = Instruction::From(From::new(replace_within(self.instructions[start].value(), alias, new)));
+        for stage in &stages[index + 1..] {
+            for idx in stage.range() {
+                instructions[idx] = rewrite_references(&self.instructions[idx], escape, |_, reference| {
+                    reference.eq_ignore_ascii_case(old).then(|| new.to_string())
+                });
+            }
+        }
+        self.instructions = instructions;
+        Ok(())
+    }
+}
+
+/// Whether the given name is one Docker accepts for a stage.
+pub(crate) fn is_valid_stage_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// The reasons a stage could not be renamed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RenameError {
+    /// No stage has the given name.
+    NotFound(String),
+    /// The given name is not a valid stage name.
+    InvalidName(String),
+    /// Another stage already has the given name.
+    StageCollision(String),
+    /// The given name already refers to an image.
+    ImageCollision(String),
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenameError::NotFound(name) => write!(f, "no stage is named {}", name),
+            RenameError::InvalidName(name) => write!(f, "invalid stage name: {}", name),
+            RenameError::StageCollision(name) => write!(f, "another stage is already named {}", name),
+            RenameError::ImageCollision(name) => write!(f, "{} already refers to an image", name),
+        }
+    }
+}
+
+impl error::Error for RenameError {}
+
+/// The ways in which an instruction may refer to another stage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    /// `FROM <stage>`.
+    From,
+    /// `COPY --from=<stage>`.
+    Copy,
+    /// `RUN --mount=from=<stage>`.
+    Mount,
+}
+
+/// Every reference the given instruction makes to another stage or image.
+pub(crate) fn stage_references(instruction: &Instruction) -> Vec<(EdgeKind, String)> {
+    let flags = match instruction {
+        Instruction::From(_) => return vec![(EdgeKind::From, split_from(instruction.value()).0.to_string())],
+        Instruction::Copy(_) | Instruction::Run(_) => match instruction.flags() {
+            Ok(flags) => flags,
+            Err(_) => return vec![],
+        },
+        _ => return vec![],
+    };
+    let mut references = vec![];
+    if let Some(from) = flags.get("from") {
+        references.push((EdgeKind::Copy, from.to_string()));
+    }
+    for mount in flags.get_all("mount") {
+        let from = mount.split(',').rev().find_map(|field| field.strip_prefix("from="));
+        if let Some(from) = from {
+            references.push((EdgeKind::Mount, from.to_string()));
+        }
+    }
+    references
+}
+
+/// Rewrite the references an instruction makes to other stages or images.
+///
+/// The given function receives each reference made by `FROM`, `COPY --from` or
+/// `RUN --mount=from=`, and returns its replacement, if any. The rest of the instruction is left
+/// exactly as it was, except that it forgets any `StageRef` it was made from.
+pub(crate) fn rewrite_references<F>(instruction: &Instruction, escape: char, rewrite: F) -> Instruction
+    where F: Fn(EdgeKind, &str) -> Option<String>,
+{
+    let instruction = &instruction.without_stage_ref();
+    let value = instruction.value();
+    if let Instruction::From(_) = instruction {
+        let image = split_from(value).0;
+        return match rewrite(EdgeKind::From, image) {
+            Some(new) => Instruction::From(From::new(replace_within(value, image, &new))),
+            None => instruction.clone(),
+        };
+    }
+    if !matches!(instruction, Instruction::Copy(_) | Instruction::Run(_)) {
+        return instruction.clone();
+    }
+    let rest = match Flags::parse(value) {
+        Ok((_, rest)) => rest,
+        Err(_) => return instruction.clone(),
+    };
+
+    let mut changed = false;
+    let mut flags = String::new();
+    for (space, word) in split_words(&value[..value.len() - rest.len()], escape) {
+        flags.push_str(space);
+        // Each word is parsed as `stage_references` parses it, so quoted references are found.
+        let parsed = Flags::parse(word).map(|(parsed, _)| parsed).unwrap_or_default();
+        let new = match parsed.iter().next().map(|flag| (flag.name(), flag.value())) {
+            Some(("from", Some(from))) => rewrite(EdgeKind::Copy, from).map(|new| format_flag("from", &new)),
+            Some(("mount", Some(mount))) => {
+                let mut rewritten = false;
+                let fields: Vec<_> = mount.split(',').map(|field| match field.strip_prefix("from=").and_then(|from| rewrite(EdgeKind::Mount, from)) {
+                    Some(new) => {
+                        rewritten = true;
+                        format!("from={}", new)
+                    }
+                    None => field.to_string(),
+                }).collect();
+                rewritten.then(|| format_flag("mount", &fields.join(",")))
+            }
+            _ => None,
+        };
+        match new {
+            Some(new) => {
+                flags.push_str(&new);
+                changed = true;
+            }
+            None => flags.push_str(word),
+        }
+    }
+    if !changed {
+        return instruction.clone();
+    }
+    flags.push(' ');
+    flags.push_str(rest);
+    parser::instruction_for(instruction.keyword(), flags).expect("every keyword other than a directive's is known")
+}
+
+/// The byte range of `part`, which must be a slice of `text`, within `text`.
+pub(crate) fn range_within(text: &str, part: &str) -> Range<usize> {
+    let start = part.as_ptr() as usize - text.as_ptr() as usize;
+    start..start + part.len()
+}
+
+/// Replace `part`, which must be a slice of `text`, with `new`.
+pub(crate) fn replace_within(text: &str, part: &str, new: &str) -> String {
+    let range = range_within(text, part);
+    format!("{}{}{}", &text[..range.start], new, &text[range.end..])
+}
+
+/// Resolve a reference to the index of the stage it names.
+///
+/// Returns `None` for references to images, and an error for numeric references out of bounds.
+pub(crate) fn resolve(stages: &[Stage<'_>], reference: &str, kind: EdgeKind) -> Option<Result<usize, ()>> {
+    let by_name = stages.iter().find(|stage| stage.name().is_some_and(|name| name.eq_ignore_ascii_case(reference)));
+    if let Some(stage) = by_name {
+        return Some(Ok(stage.index()));
+    }
+    match (kind, reference.parse::<usize>()) {
+        (EdgeKind::From, _) | (_, Err(_)) => None,
+        (_, Ok(index)) if index < stages.len() => Some(Ok(index)),
+        (_, Ok(_)) => Some(Err(())),
+    }
+}
+
+/// An invalid reference from one stage of a Dockerfile to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StageRefError {
+    /// More than one stage has the given name.
+    Duplicate(String),
+    /// The given stage name is not one Docker accepts.
+    InvalidName(String),
+    /// A numeric reference to a stage which does not exist.
+    Missing {
+        reference: String,
+        stage: usize,
+    },
+    /// A reference to the stage making it, or to a later stage.
+    Forward {
+        reference: String,
+        stage: usize,
+        target: usize,
+    },
+    /// An instruction made from a `StageRef` to a stage which is not in this Dockerfile, such as
+    /// one added to another builder.
+    ForeignStage {
+        reference: String,
+        stage: usize,
+        target: usize,
+    },
+}
+
+impl fmt::Display for StageRefError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StageRefError::Duplicate(name) => write!(f, "duplicate stage name: {}", name),
+            StageRefError::InvalidName(name) => write!(f, "invalid stage name: {}", name),
+            StageRefError::Missing{reference, stage} => write!(f, "stage {} refers to stage {}, which does not exist", stage, reference),
+            StageRefError::Forward{reference, stage, target} if stage == target => write!(f, "stage {} refers to itself as {}", stage, reference),
+            StageRefError::Forward{reference, stage, target} => {
+                write!(f, "stage {} refers to {}, which is the later stage {}", stage, reference, target)
+            }
+            StageRefError::ForeignStage{reference, stage, target} => {
+                write!(f, "stage {} refers to {} as stage {}, which is not a stage of this Dockerfile", stage, reference, target)
+            }
+        }
+    }
+}
+
+impl error::Error for StageRefError {}
+
+/// A handle to a stage added to a `DockerfileBuilder`, for use by later stages.
+///
+/// Instructions made from a handle, with `Copy::from_stage` and `From::stage`, always spell the
+/// name of the stage correctly. `DockerfileBuilder::try_finish` checks that each of them still
+/// refers to an earlier stage of the same Dockerfile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageRef {
+    name: String,
+    index: usize,
+}
+
+impl StageRef {
+    /// The name of the stage.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The position of the stage among the stages of its Dockerfile, starting from 0.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// Split the arguments of a `FROM` instruction into the image and the stage name, if any.
+pub(crate) fn split_from(value: &str) -> (&str, Option<&str>) {
+    let args = Flags::parse(value).map(|(_, rest)| rest).unwrap_or(value);
+    let mut words = args.split_whitespace();
+    let image = words.next().unwrap_or("");
+    match (words.next(), words.next(), words.next()) {
+        (Some(as_), Some(name), None) if as_.eq_ignore_ascii_case("as") => (image, Some(name)),
+        _ => (image, None),
+    }
+}
+
+/// A builder for a single named build stage, used with `DockerfileBuilder::stage`.
+pub struct StageBuilder {
+    /// The stage's `FROM` instruction.
+    pub(crate) from: (From, Provenance),
+
+    /// The instructions of the stage which follow its `FROM`.
+    pub(crate) instructions: Vec<(Instruction, Provenance)>,
+}
+
+impl StageBuilder {
+    /// Push a new instruction into this stage.
+    #[track_caller]
+    pub fn push<I: Into<Instruction>>(mut self, instruction: I) -> Self {
+        self.instructions.push((instruction.into(), Location::caller()));
+        self
+    }
+
+    /// Append a vector of instructions to this stage.
+    #[track_caller]
+    pub fn append<I: Into<Instruction>>(mut self, new: Vec<I>) -> Self {
+        let location = Location::caller();
+        self.instructions.extend(new.into_iter().map(|inst| (inst.into(), location)));
+        self
+    }
+}
+
+impl DockerfileBuilder {
+    /// Add a new build stage named `name`, built upon `base`.
+    ///
+    /// The given closure receives a `StageBuilder` to which the stage's instructions are pushed.
+    /// A `name` Docker does not accept is reported by `try_finish`.
+    #[track_caller]
+    pub fn stage<N, B, F>(self, name: N, base: B, build: F) -> Self
+        where N: Into<Cow<'static, str>>, B: Into<Cow<'static, str>>, F: FnOnce(StageBuilder) -> StageBuilder,
+    {
+        self.add_stage(name, base, build).0
+    }
+
+    /// Add a new build stage named `name`, built upon `base`, returning a handle to it as well.
+    ///
+    /// This is `stage`, for when later stages need to refer to this one.
+    #[track_caller]
+    pub fn add_stage<N, B, F>(self, name: N, base: B, build: F) -> (Self, StageRef)
+        where N: Into<Cow<'static, str>>, B: Into<Cow<'static, str>>, F: FnOnce(StageBuilder) -> StageBuilder,
+    {
+        let name = name.into();
+        let handle = StageRef{name: name.to_string(), index: self.stage_count()};
+        let from = From::new(format!("{} AS {}", base.into(), name));
+        let stage = build(StageBuilder{from: (from, Location::caller()), instructions: vec![]});
+        let (from, location) = stage.from;
+        let mut builder = self.push_located(Instruction::From(from), location);
+        for (instruction, location) in stage.instructions {
+            builder = builder.push_located(instruction, location);
+        }
+        (builder, handle)
+    }
+
+    /// Get a handle to the stage added so far with the given name, such as one named in the base
+    /// image given to `Dockerfile::base`.
+    pub fn stage_ref(&self, name: &str) -> Option<StageRef> {
+        let base = Instruction::From(self.from.0.clone());
+        std::iter::once(&base)
+            .chain(self.instructions.iter().flatten().map(|(inst, _)| inst))
+            .filter(|inst| matches!(inst, Instruction::From(_)))
+            .enumerate()
+            .find_map(|(index, from)| match split_from(from.value()).1 {
+                Some(found) if found.eq_ignore_ascii_case(name) => Some(StageRef{name: found.to_string(), index}),
+                _ => None,
+            })
+    }
+
+    /// Generate the output Dockerfile, checking its references between stages.
+    ///
+    /// See `Dockerfile::check_stage_refs` for the mistakes which are caught.
+    pub fn try_finish(self) -> Result<Dockerfile, StageRefError> {
+        let dockerfile = self.finish();
+        dockerfile.check_stage_refs()?;
+        Ok(dockerfile)
+    }
+
+    /// The number of stages added so far.
+    fn stage_count(&self) -> usize {
+        1 + self.instructions.iter().flatten().filter(|(inst, _)| matches!(inst, Instruction::From(_))).count()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instructions::{
+        Arg,
+        Cmd,
+        Copy,
+        Run,
+    };
+
+    #[test]
+    fn stage_builder_adds_named_stages() {
+        let line = line!();
+        let dockerfile = Dockerfile::base("rust:1.31 AS builder")
+            .push(Run::new("cargo build --release"))
+            .stage("runtime", "debian:stable-slim", |s| s
+                .push(Copy::new("--from=builder /app/target/release/app /usr/local/bin/"))
+                .push(Cmd::new("[\"app\"]")))
+            .finish();
+        assert_eq!(dockerfile.to_string(), r##"FROM rust:1.31 AS builder
+RUN cargo build --release
+FROM debian:stable-slim AS runtime
+COPY --from=builder /app/target/release/app /usr/local/bin/
+CMD ["app"]
+"##);
+        assert_eq!(dockerfile.provenance(2).map(|loc| loc.line()), Some(line + 3));
+        assert_eq!(dockerfile.provenance(3).map(|loc| loc.line()), Some(line + 4));
+    }
+
+    #[test]
+    fn stages_partition_instructions() {
+        let dockerfile = Dockerfile::parse("# syntax=docker/dockerfile:1\nARG V=1\nFROM --platform=$BUILDPLATFORM rust:$V as Builder\nRUN make\nFROM alpine\nCOPY --from=builder /app /app\n").unwrap();
+        let stages = dockerfile.stages();
+        assert_eq!(stages.len(), 2);
+
+        assert_eq!((stages[0].index(), stages[0].name(), stages[0].base()), (0, Some("Builder"), "rust:$V"));
+        assert_eq!(stages[0].instructions(), &[Instruction::Run(Run::new("make"))]);
+        assert_eq!(stages[0].range(), 2..4);
+
+        assert_eq!((stages[1].index(), stages[1].name(), stages[1].base()), (1, None, "alpine"));
+        assert_eq!(stages[1].from(), &From::new("alpine"));
+        assert_eq!(stages[1].range(), 4..6);
+
+        let owners: Vec<_> = (0..7).map(|idx| dockerfile.stage_of(idx)).collect();
+        assert_eq!(owners, vec![None, None, Some(0), Some(0), Some(1), Some(1), None]);
+    }
+
+    #[test]
+    fn stages_of_legacy_builder_output() {
+        let dockerfile = Dockerfile::base("rust:1.31-slim")
+            .push_initial_arg(Arg::new("A=1"))
+            .push(From::new("rust:1.31-slim as other"))
+            .push(Cmd::new("echo 'Hello, world.'"))
+            .finish();
+        let stages = dockerfile.stages();
+        assert_eq!(stages.iter().map(|s| s.name()).collect::<Vec<_>>(), vec![None, Some("other")]);
+        assert!(stages[0].instructions().is_empty());
+        assert_eq!(stages[1].instructions().len(), 1);
+    }
+
+    #[test]
+    fn stage_refs_are_checked() {
+        let builder = Dockerfile::base("rust:1.31 AS builder");
+        let build = builder.stage_ref("BUILDER").unwrap();
+        assert_eq!((build.name(), build.index()), ("builder", 0));
+        let (builder, assets) = builder.add_stage("assets", "node:20", |s| s.push(Run::new("npm run build")));
+        assert_eq!((assets.name(), assets.index()), ("assets", 1));
+        let dockerfile = builder
+            .stage("runtime", "debian:stable-slim", |s| s
+                .push(Copy::from_stage(&build, "/app/target/release/app /usr/local/bin/"))
+                .push(Copy::from_stage(&assets, "/dist /srv")))
+            .push(From::stage(&assets))
+            .try_finish()
+            .unwrap();
+        assert_eq!(dockerfile.to_string(), r##"FROM rust:1.31 AS builder
+FROM node:20 AS assets
+RUN npm run build
+FROM debian:stable-slim AS runtime
+COPY --from=builder /app/target/release/app /usr/local/bin/
+COPY --from=assets /dist /srv
+FROM assets
+"##);
+    }
+
+    #[test]
+    fn stage_ref_errors() {
+        let check = |input: &str| Dockerfile::parse(input).unwrap().check_stage_refs();
+        assert_eq!(check("FROM alpine AS a\nCOPY --from=nginx:latest /a /a\nFROM a\nCOPY --from=0 /a /a\n"), Ok(()));
+        assert_eq!(check("FROM alpine AS a\nFROM alpine AS A\n"), Err(StageRefError::Duplicate("A".into())));
+        assert_eq!(
+            check("FROM alpine AS a\nCOPY --from=b /b /b\nFROM alpine AS b\n"),
+            Err(StageRefError::Forward{reference: "b".into(), stage: 0, target: 1}),
+        );
+        assert_eq!(
+            check("FROM alpine AS a\nRUN --mount=type=bind,from=a,target=/a ls\n"),
+            Err(StageRefError::Forward{reference: "a".into(), stage: 0, target: 0}),
+        );
+        assert_eq!(check("FROM alpine\nCOPY --from=3 /a /a\n"), Err(StageRefError::Missing{reference: "3".into(), stage: 0}));
+
+        let (builder, later) = Dockerfile::base("alpine").add_stage("later", "alpine", |s| s);
+        let err = builder.push(Copy::from_stage(&later, "/a /a")).try_finish().unwrap_err();
+        assert_eq!(err.to_string(), "stage 1 refers to itself as later");
+
+        assert_eq!(check("FROM alpine AS 1st\n"), Err(StageRefError::InvalidName("1st".into())));
+        let err = Dockerfile::base("alpine").stage("build/app", "alpine", |s| s).try_finish().unwrap_err();
+        assert_eq!(err.to_string(), "invalid stage name: build/app");
+    }
+
+    #[test]
+    fn stage_refs_from_other_builders_are_caught() {
+        let (_, build) = Dockerfile::base("alpine").add_stage("build", "rust:1.75", |s| s);
+        let err = Dockerfile::base("alpine").push(Copy::from_stage(&build, "/app /app")).try_finish().unwrap_err();
+        assert_eq!(err, StageRefError::ForeignStage{reference: "build".into(), stage: 0, target: 1});
+        assert_eq!(err.to_string(), "stage 0 refers to build as stage 1, which is not a stage of this Dockerfile");
+
+        // A stage of the same name at another position is not the stage the handle was taken from.
+        let (_, other) = Dockerfile::base("alpine").stage("x", "alpine", |s| s).add_stage("build", "alpine", |s| s);
+        let err = Dockerfile::base("alpine AS build").push(From::stage(&other)).try_finish().unwrap_err();
+        assert_eq!(err, StageRefError::ForeignStage{reference: "build".into(), stage: 1, target: 2});
+
+        let builder = Dockerfile::base("alpine AS build");
+        let own = builder.stage_ref("build").unwrap();
+        assert!(builder.push(From::stage(&own)).push(Copy::from_stage(&own, "/a /a")).try_finish().is_ok());
+    }
+
+    #[test]
+    fn rename_stage_rewrites_references() {
+        let mut dockerfile = Dockerfile::parse(r##"FROM alpine AS tools
+FROM --platform=$BUILDPLATFORM rust:1.75 as Builder
+COPY --from=tools /bin/tool /bin/tool
+FROM builder AS test
+RUN --mount=type=bind,from=builder,target=/src cargo test
+FROM alpine
+COPY --link --from=BUILDER /app /app
+COPY --from=0 /bin/tool /bin/tool
+COPY --from="builder" /app /app
+RUN --mount="type=cache,target=/cache" --mount='type=bind,from=builder,target=/src' make
+"##).unwrap();
+        dockerfile.rename_stage("builder", "compile").unwrap();
+        assert_eq!(dockerfile.to_string(), r##"FROM alpine AS tools
+FROM --platform=$BUILDPLATFORM rust:1.75 as compile
+COPY --from=tools /bin/tool /bin/tool
+FROM compile AS test
+RUN --mount=type=bind,from=compile,target=/src cargo test
+FROM alpine
+COPY --link --from=compile /app /app
+COPY --from=0 /bin/tool /bin/tool
+COPY --from=compile /app /app
+RUN --mount="type=cache,target=/cache" --mount=type=bind,from=compile,target=/src make
+"##);
+        assert_eq!(dockerfile.check_stage_refs(), Ok(()));
+    }
+
+    #[test]
+    fn rename_stage_errors() {
+        let mut dockerfile = Dockerfile::parse("FROM alpine AS a\nFROM alpine AS b\nCOPY --from=nginx /a /a\n").unwrap();
+        let original = dockerfile.clone();
+        assert_eq!(dockerfile.rename_stage("c", "d"), Err(RenameError::NotFound("c".into())));
+        assert_eq!(dockerfile.rename_stage("a", "B"), Err(RenameError::StageCollision("B".into())));
+        assert_eq!(dockerfile.rename_stage("a", "alpine"), Err(RenameError::ImageCollision("alpine".into())));
+        assert_eq!(dockerfile.rename_stage("a", "nginx"), Err(RenameError::ImageCollision("nginx".into())));
+        assert_eq!(dockerfile.rename_stage("a", "1st"), Err(RenameError::InvalidName("1st".into())));
+        assert_eq!(dockerfile, original);
+        assert_eq!(dockerfile.rename_stage("a", "A"), Ok(()));
+        assert_eq!(dockerfile.stages()[0].name(), Some("A"));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    builder::Dockerfile,
+    diagnostic::Diagnostic,
+    instructions::Instruction,
+    parser,
+};
+
+impl Dockerfile {
+    /// Check this Dockerfile against the structural rules Docker enforces or warns about.
+    ///
+    /// Besides the problems the parser reports with individual instructions, such as unknown
+    /// flags or a `SHELL` not in JSON form, this reports:
+    ///
+    /// - a Dockerfile without any `FROM`
+    /// - instructions other than `ARG` before the first `FROM`
+    /// - stages sharing a name
+    /// - more than one `CMD`, `ENTRYPOINT` or `HEALTHCHECK` in a stage, of which only the last
+    ///   takes effect
+    ///
+    /// Diagnostics refer to the line each instruction was parsed from, or for built Dockerfiles,
+    /// the line on which it is rendered. They are ordered by line.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let lines = self.report_lines();
+        let mut diagnostics = vec![];
+        for (idx, instruction) in self.instructions().iter().enumerate() {
+            diagnostics.extend(parser::check_instruction(lines[idx], instruction));
+        }
+
+        let stages = self.stages();
+        let preamble = match stages.first() {
+            Some(stage) => stage.range().start,
+            None => {
+                diagnostics.push(Diagnostic::error(1, "no FROM instruction found, every Dockerfile needs at least one"));
+                self.instructions().len()
+            }
+        };
+        for (idx, instruction) in self.instructions()[..preamble].iter().enumerate() {
+            if !matches!(instruction, Instruction::Arg(_) | Instruction::Directive(_)) {
+                let message = format!("no build stage in current context: {} must follow a FROM instruction", instruction.keyword());
+                diagnostics.push(Diagnostic::error(lines[idx], message));
+            }
+        }
+
+        for (idx, stage) in stages.iter().enumerate() {
+            let line = lines[stage.range().start];
+            if let Some(name) = stage.name() {
+                if stages[..idx].iter().any(|prev| prev.name().is_some_and(|prev| prev.eq_ignore_ascii_case(name))) {
+                    diagnostics.push(Diagnostic::error(line, format!("duplicate stage name: {}", name)));
+                }
+            }
+            for keyword in &["CMD", "ENTRYPOINT", "HEALTHCHECK"] {
+                let found: Vec<_> = stage.range().filter(|&idx| self.instructions()[idx].keyword() == *keyword).collect();
+                if let Some((_, earlier)) = found.split_last() {
+                    for &idx in earlier {
+                        let message = format!("multiple {} instructions in the same stage, only the last one will be used", keyword);
+                        diagnostics.push(Diagnostic::warning(lines[idx], message));
+                    }
+                }
+            }
+        }
+
+        diagnostics.sort_by_key(|diag| diag.line);
+        diagnostics
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+// Unit Tests ////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instructions::{
+        Cmd,
+        Env,
+        From,
+        Healthcheck,
+        Run,
+        Shell,
+    };
+
+    #[test]
+    fn validate_reports_structural_problems() {
+        let dockerfile = Dockerfile::parse(r##"# syntax=docker/dockerfile:1
+ARG A=1
+ENV B=2
+FROM alpine AS build
+CMD ["a"]
+CMD ["b"]
+FROM alpine AS Build
+HEALTHCHECK NONE
+HEALTHCHECK CMD true
+"##).unwrap();
+        assert_eq!(dockerfile.validate(), vec![
+            Diagnostic::error(3, "no build stage in current context: ENV must follow a FROM instruction"),
+            Diagnostic::warning(5, "multiple CMD instructions in the same stage, only the last one will be used"),
+            Diagnostic::error(7, "duplicate stage name: Build"),
+            Diagnostic::warning(8, "multiple HEALTHCHECK instructions in the same stage, only the last one will be used"),
+        ]);
+    }
+
+    #[test]
+    fn validate_built_dockerfiles() {
+        let dockerfile = Dockerfile::base("alpine")
+            .push(Run::new(""))
+            .push(Shell::new("/bin/bash -c"))
+            .push(Cmd::new("['a']"))
+            .push(Cmd::new("b"))
+            .finish();
+        assert_eq!(dockerfile.validate(), vec![
+            Diagnostic::error(2, "RUN requires at least one argument"),
+            Diagnostic::error(3, "SHELL requires the arguments to be in JSON form"),
+            Diagnostic::warning(4, "CMD arguments look like a JSON array, but are not valid JSON and will be treated as shell form"),
+            Diagnostic::warning(4, "multiple CMD instructions in the same stage, only the last one will be used"),
+        ]);
+
+        let no_from = Dockerfile::from_instructions(vec![Env::new("A=1").into(), Healthcheck::new("NONE").into()]);
+        assert_eq!(no_from.validate(), vec![
+            Diagnostic::error(1, "no FROM instruction found, every Dockerfile needs at least one"),
+            Diagnostic::error(1, "no build stage in current context: ENV must follow a FROM instruction"),
+            Diagnostic::error(2, "no build stage in current context: HEALTHCHECK must follow a FROM instruction"),
+        ]);
+        assert!(Dockerfile::from_instructions(vec![From::new("alpine").into()]).validate().is_empty());
+    }
+}